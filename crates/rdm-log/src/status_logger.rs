@@ -26,6 +26,17 @@ impl StatusLogger {
         );
     }
 
+    pub fn new_unsaved_file(path: &str) {
+        let indent = 4usize;
+        println!(
+            "{:indent$}{}{} {}",
+            "",
+            Color::Green.paint("[new file]"),
+            Color::Red.paint("(unsaved)"),
+            path
+        );
+    }
+
     pub fn modified_file(path: &str) {
         let indent = 4usize;
         println!(
@@ -47,6 +58,17 @@ impl StatusLogger {
         );
     }
 
+    pub fn modified_rendered_file(path: &str) {
+        let indent = 4usize;
+        println!(
+            "{:indent$}{}{} {}",
+            "",
+            Color::Yellow.paint("[modified]"),
+            Color::Red.paint("(rendered)"),
+            path
+        );
+    }
+
     pub fn untracked_file(path: &str) {
         let indent = 4usize;
         println!("{:indent$}{} {}", "", Color::Red.paint("[untracked]"), path);
@@ -67,4 +89,44 @@ impl StatusLogger {
             path
         );
     }
+
+    pub fn undeployed_file(path: &str) {
+        let indent = 4usize;
+        println!(
+            "{:indent$}{} {}",
+            "",
+            Color::Purple.paint("[undeployed]"),
+            path
+        );
+    }
+
+    pub fn missing_link(path: &str) {
+        let indent = 4usize;
+        println!(
+            "{:indent$}{} {}",
+            "",
+            Color::Purple.paint("[missing link]"),
+            path
+        );
+    }
+
+    pub fn broken_link(path: &str) {
+        let indent = 4usize;
+        println!(
+            "{:indent$}{} {}",
+            "",
+            Color::Red.paint("[broken link]"),
+            path
+        );
+    }
+
+    pub fn foreign_link(path: &str) {
+        let indent = 4usize;
+        println!(
+            "{:indent$}{} {}",
+            "",
+            Color::Yellow.paint("[not a link]"),
+            path
+        );
+    }
 }
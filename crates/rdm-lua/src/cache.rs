@@ -0,0 +1,247 @@
+use std::{
+    fs::File,
+    io::Read,
+    path::{Path, PathBuf},
+    time::SystemTime,
+};
+
+use sha2::{Digest, Sha256};
+
+/// The downloads of `curl` made with an expected checksum, kept to be reused.
+/// The files are stored by checksum under `blobs`, and `index` lists the URLs
+/// they were downloaded from.
+#[derive(Debug, Clone)]
+pub struct DownloadCache {
+    dir: PathBuf,
+    /// Whether a download missing from the cache fails instead of being made.
+    pub offline: bool,
+}
+
+/// A file of the download cache.
+#[derive(Debug)]
+pub struct CacheEntry {
+    pub url: String,
+    pub sha256: String,
+    pub size: u64,
+    /// When the file was last downloaded or reused.
+    pub last_used: SystemTime,
+}
+
+/// The SHA-256 checksum of the file at `path`, in lowercase hexadecimal.
+fn file_sha256(path: &Path) -> Result<String, std::io::Error> {
+    let mut file = File::open(path)?;
+    let mut hasher = Sha256::new();
+    let mut buf = vec![0; 64 * 1024];
+
+    loop {
+        match file.read(&mut buf)? {
+            0 => break,
+            read => hasher.update(&buf[..read]),
+        }
+    }
+
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+impl DownloadCache {
+    pub fn new(dir: PathBuf, offline: bool) -> DownloadCache {
+        DownloadCache { dir, offline }
+    }
+
+    fn index_path(&self) -> PathBuf {
+        self.dir.join("index")
+    }
+
+    /// Where the file whose checksum is `sha256` is stored.
+    pub(crate) fn blob_path(&self, sha256: &str) -> PathBuf {
+        self.dir.join("blobs").join(sha256)
+    }
+
+    /// The checksums and URLs of the cached downloads.
+    fn index(&self) -> Result<Vec<(String, String)>, std::io::Error> {
+        let path = self.index_path();
+
+        if !path.exists() {
+            return Ok(Vec::new());
+        }
+
+        Ok(std::fs::read_to_string(path)?
+            .lines()
+            .filter_map(|line| line.split_once('\t'))
+            .map(|(sha256, url)| (sha256.to_string(), url.to_string()))
+            .collect())
+    }
+
+    fn set_index(
+        &self,
+        index: &[(String, String)],
+    ) -> Result<(), std::io::Error> {
+        let lines: Vec<String> = index
+            .iter()
+            .map(|(sha256, url)| format!("{}\t{}", sha256, url))
+            .collect();
+        std::fs::create_dir_all(&self.dir)?;
+        std::fs::write(self.index_path(), lines.join("\n"))
+    }
+
+    /// The cached download of `url` whose checksum is `sha256`, if any. It is
+    /// marked as used, and dropped if it does not match its checksum anymore.
+    pub(crate) fn get(
+        &self,
+        url: &str,
+        sha256: &str,
+    ) -> Result<Option<PathBuf>, std::io::Error> {
+        let blob = self.blob_path(sha256);
+
+        if !blob.exists()
+            || !self.index()?.iter().any(|(s, u)| s == sha256 && u == url)
+        {
+            return Ok(None);
+        }
+
+        if file_sha256(&blob)? != sha256 {
+            log::warn!(
+                "The cached download of {} is corrupted, it is downloaded \
+                 again.",
+                url
+            );
+            std::fs::remove_file(&blob)?;
+            return Ok(None);
+        }
+
+        File::options()
+            .write(true)
+            .open(&blob)?
+            .set_modified(SystemTime::now())?;
+
+        Ok(Some(blob))
+    }
+
+    /// Records that the file stored for `sha256` was downloaded from `url`.
+    pub(crate) fn record(
+        &self,
+        url: &str,
+        sha256: &str,
+    ) -> Result<(), std::io::Error> {
+        let mut index = self.index()?;
+
+        if !index.iter().any(|(s, u)| s == sha256 && u == url) {
+            index.push((sha256.to_string(), url.to_string()));
+            self.set_index(&index)?;
+        }
+
+        Ok(())
+    }
+
+    /// The cached downloads, from the least recently used.
+    pub fn entries(&self) -> Result<Vec<CacheEntry>, std::io::Error> {
+        let mut entries = Vec::new();
+
+        for (sha256, url) in self.index()? {
+            let metadata = match std::fs::metadata(self.blob_path(&sha256)) {
+                Ok(metadata) => metadata,
+                Err(err) if err.kind() == std::io::ErrorKind::NotFound => {
+                    continue
+                }
+                Err(err) => return Err(err),
+            };
+
+            entries.push(CacheEntry {
+                url,
+                sha256,
+                size: metadata.len(),
+                last_used: metadata.modified()?,
+            });
+        }
+        entries.sort_by_key(|entry| entry.last_used);
+
+        Ok(entries)
+    }
+
+    /// Removes `entry` from the cache, its file is removed once no other URL
+    /// uses it.
+    pub fn remove(&self, entry: &CacheEntry) -> Result<(), std::io::Error> {
+        let mut index = self.index()?;
+        index.retain(|(sha256, url)| {
+            !(sha256 == &entry.sha256 && url == &entry.url)
+        });
+        self.set_index(&index)?;
+
+        if !index.iter().any(|(sha256, _)| sha256 == &entry.sha256) {
+            let blob = self.blob_path(&entry.sha256);
+            if blob.exists() {
+                std::fs::remove_file(blob)?;
+            }
+        }
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// The SHA-256 checksum of "hello\n".
+    const HELLO_SHA256: &str =
+        "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+
+    /// A cache in an empty directory for the test `name`, holding "hello\n".
+    fn test_cache(name: &str) -> DownloadCache {
+        let dir = std::env::temp_dir().join(format!(
+            "rdm-cache-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        let cache = DownloadCache::new(dir, false);
+
+        let blob = cache.blob_path(HELLO_SHA256);
+        std::fs::create_dir_all(blob.parent().unwrap()).unwrap();
+        std::fs::write(blob, "hello\n").unwrap();
+
+        cache
+    }
+
+    #[test]
+    fn get_recorded_download() {
+        let cache = test_cache("get");
+        assert_eq!(cache.get("file:///a", HELLO_SHA256).unwrap(), None);
+
+        cache.record("file:///a", HELLO_SHA256).unwrap();
+
+        assert_eq!(
+            cache.get("file:///a", HELLO_SHA256).unwrap(),
+            Some(cache.blob_path(HELLO_SHA256))
+        );
+        assert_eq!(cache.get("file:///b", HELLO_SHA256).unwrap(), None);
+    }
+
+    #[test]
+    fn get_drops_corrupted_download() {
+        let cache = test_cache("corrupted");
+        cache.record("file:///a", HELLO_SHA256).unwrap();
+        std::fs::write(cache.blob_path(HELLO_SHA256), "changed\n").unwrap();
+
+        assert_eq!(cache.get("file:///a", HELLO_SHA256).unwrap(), None);
+        assert!(!cache.blob_path(HELLO_SHA256).exists());
+    }
+
+    #[test]
+    fn remove_keeps_file_used_by_another_url() {
+        let cache = test_cache("remove");
+        cache.record("file:///a", HELLO_SHA256).unwrap();
+        cache.record("file:///b", HELLO_SHA256).unwrap();
+
+        let entries = cache.entries().unwrap();
+        assert_eq!(entries.len(), 2);
+
+        cache.remove(&entries[0]).unwrap();
+        assert!(cache.blob_path(HELLO_SHA256).exists());
+        assert_eq!(cache.entries().unwrap().len(), 1);
+
+        cache.remove(&entries[1]).unwrap();
+        assert!(!cache.blob_path(HELLO_SHA256).exists());
+        assert!(cache.entries().unwrap().is_empty());
+    }
+}
@@ -0,0 +1,165 @@
+use std::{ffi::CStr, path::Path};
+
+use gethostname::gethostname;
+use mlua::{Error, Lua, Table};
+use serde::Serialize;
+
+/// What rdm knows about the machine it runs on, exposed to Lua as the
+/// read-only `rdm.facts` table.
+#[derive(Debug, Clone, Serialize)]
+pub struct Facts {
+    pub hostname: String,
+    /// The host branch HEAD is on, if any.
+    pub host: Option<String>,
+    /// The operating system, such as `linux` or `macos`.
+    pub os: String,
+    /// The release of the kernel, as given by `uname -r`.
+    pub kernel: Option<String>,
+    /// The architecture, such as `x86_64` or `aarch64`.
+    pub arch: String,
+    /// The `ID` of `/etc/os-release`, such as `arch` or `debian`.
+    pub distro: Option<String>,
+    /// The `VERSION_ID` of `/etc/os-release`.
+    pub distro_version: Option<String>,
+    pub user: Option<String>,
+    pub home: Option<String>,
+    pub shell: Option<String>,
+    pub container: bool,
+    pub wsl: bool,
+    /// The version of rdm.
+    pub version: String,
+}
+
+/// The release of the running kernel.
+fn kernel_release() -> Option<String> {
+    let mut uts = unsafe { std::mem::zeroed::<libc::utsname>() };
+
+    if unsafe { libc::uname(&mut uts) } != 0 {
+        return None;
+    }
+
+    let release = unsafe { CStr::from_ptr(uts.release.as_ptr()) };
+    Some(release.to_string_lossy().to_string())
+}
+
+/// The name of the user running rdm, from the environment or the passwd
+/// database.
+fn user_name() -> Option<String> {
+    if let Ok(user) =
+        std::env::var("USER").or_else(|_| std::env::var("LOGNAME"))
+    {
+        return Some(user);
+    }
+
+    let passwd = unsafe { libc::getpwuid(libc::getuid()) };
+    if passwd.is_null() {
+        return None;
+    }
+
+    let name = unsafe { CStr::from_ptr((*passwd).pw_name) };
+    Some(name.to_string_lossy().to_string())
+}
+
+/// The value of `key` in the content of an `os-release` file.
+fn os_release_value(os_release: &str, key: &str) -> Option<String> {
+    os_release.lines().find_map(|line| {
+        let value = line.strip_prefix(key)?.strip_prefix('=')?;
+        Some(
+            value
+                .trim()
+                .trim_matches(|c| c == '"' || c == '\'')
+                .to_string(),
+        )
+    })
+}
+
+fn in_container() -> bool {
+    if std::env::var_os("container").is_some()
+        || Path::new("/.dockerenv").exists()
+        || Path::new("/run/.containerenv").exists()
+    {
+        return true;
+    }
+
+    match std::fs::read_to_string("/proc/1/cgroup") {
+        Ok(cgroup) => ["docker", "lxc", "kubepods", "containerd"]
+            .iter()
+            .any(|name| cgroup.contains(name)),
+        Err(_) => false,
+    }
+}
+
+fn in_wsl(kernel: &Option<String>) -> bool {
+    std::env::var_os("WSL_DISTRO_NAME").is_some()
+        || kernel
+            .as_ref()
+            .is_some_and(|k| k.to_lowercase().contains("microsoft"))
+}
+
+impl Facts {
+    /// Gathers the facts of the machine, `host` and `version` being given by
+    /// rdm.
+    pub fn gather(host: Option<String>, version: &str) -> Facts {
+        let kernel = kernel_release();
+        let os_release = std::fs::read_to_string("/etc/os-release")
+            .or_else(|_| std::fs::read_to_string("/usr/lib/os-release"))
+            .unwrap_or_default();
+
+        Facts {
+            hostname: gethostname().to_string_lossy().to_string(),
+            host,
+            os: std::env::consts::OS.to_string(),
+            wsl: in_wsl(&kernel),
+            kernel,
+            arch: std::env::consts::ARCH.to_string(),
+            distro: os_release_value(&os_release, "ID"),
+            distro_version: os_release_value(&os_release, "VERSION_ID"),
+            user: user_name(),
+            home: std::env::var("HOME").ok(),
+            shell: std::env::var("SHELL").ok(),
+            container: in_container(),
+            version: version.to_string(),
+        }
+    }
+
+    /// Builds the read-only Lua table of the facts.
+    pub(crate) fn to_lua<'lua>(
+        &self,
+        lua: &'lua Lua,
+    ) -> Result<Table<'lua>, Error> {
+        let facts = lua.create_table()?;
+
+        facts.set("hostname", self.hostname.as_str())?;
+        facts.set("host", self.host.clone())?;
+        facts.set("os", self.os.as_str())?;
+        facts.set("kernel", self.kernel.clone())?;
+        facts.set("arch", self.arch.as_str())?;
+        facts.set("distro", self.distro.clone())?;
+        facts.set("distro_version", self.distro_version.clone())?;
+        facts.set("user", self.user.clone())?;
+        facts.set("home", self.home.clone())?;
+        facts.set("shell", self.shell.clone())?;
+        facts.set("container", self.container)?;
+        facts.set("wsl", self.wsl)?;
+        facts.set("version", self.version.as_str())?;
+
+        // The facts are only reachable through the metatable of an empty
+        // proxy, which refuses new fields.
+        let meta = lua.create_table()?;
+        meta.set("__index", facts)?;
+        meta.set(
+            "__newindex",
+            lua.create_function(
+                |_, _: mlua::MultiValue| -> Result<(), Error> {
+                    Err(Error::external("`rdm.facts' is read-only."))
+                },
+            )?,
+        )?;
+        meta.set("__metatable", false)?;
+
+        let proxy = lua.create_table()?;
+        proxy.set_metatable(Some(meta));
+
+        Ok(proxy)
+    }
+}
@@ -0,0 +1,57 @@
+use std::path::PathBuf;
+
+use mlua::{Error, Function, Lua, Table};
+use rdm_macros::{FromError, ToDoc};
+
+use crate::manifest::{self, FileEntry};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "Error while initializing the `copy' function: "]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub enum CopyFunctionError {
+    #[doc_to_string]
+    LuaError(mlua::Error),
+}
+
+/// Options accepted by `copy`.
+const OPTIONS: [&str; 2] = ["source", "target"];
+
+/// Declares a file of the worktree copied to an absolute path, which may be
+/// outside of the worktree:
+/// `copy{ source = "etc/hosts.d/dev", target = "/etc/hosts.d/dev" }`.
+pub fn copy_fn(
+    lua: &Lua,
+    worktree_path: PathBuf,
+) -> Result<Function<'_>, CopyFunctionError> {
+    let copy_fn = lua.create_function(move |lua, table: Table| {
+        manifest::check_options(&table, "copy", &OPTIONS, 0)?;
+
+        let (source, target) = match (
+            table.get::<_, Option<String>>("source")?,
+            table.get::<_, Option<String>>("target")?,
+        ) {
+            (Some(source), Some(target)) => (source, target),
+            _ => {
+                return Err(Error::external(
+                    "`copy' needs a `source' and a `target'.",
+                ))
+            }
+        };
+
+        let target = manifest::expand_home(&target);
+        if target.is_relative() {
+            return Err(Error::external(format!(
+                "The target {} of `copy' must be an absolute path.",
+                target.display()
+            )));
+        }
+
+        let mut entry =
+            FileEntry::new(manifest::resolve(&source, &worktree_path)?);
+        entry.target = Some(target);
+
+        manifest::with_manifest(lua, |manifest| manifest.copies.push(entry))
+    })?;
+
+    Ok(copy_fn)
+}
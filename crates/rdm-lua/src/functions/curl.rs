@@ -1,6 +1,19 @@
-use curl::easy::Easy;
-use mlua::Lua;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    fs::{File, Permissions},
+    io::Write,
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+    time::Duration,
+};
+
+use curl::easy::{Easy, List};
+use mlua::{Error, Lua, Table, Value};
 use rdm_macros::{FromError, ToDoc};
+use sha2::{Digest, Sha256};
+
+use crate::{cache::DownloadCache, manifest};
 
 #[derive(Debug, FromError, ToDoc)]
 #[doc_prefix = "Error while initializing the `curl' function: "]
@@ -8,37 +21,579 @@ use rdm_macros::{FromError, ToDoc};
 pub enum CurlFunctionError {
     #[doc_to_string]
     LuaError(mlua::Error),
-    #[doc_to_string]
-    CurlError(curl::Error),
 }
 
-pub fn curl_fn(lua: &Lua) -> Result<mlua::Function, CurlFunctionError> {
-    let fun = lua.create_function(|_, url: String| {
-        let mut easy = Easy::new();
-        let mut buf = Vec::new();
+/// Why a download made by `curl` failed, raised as a Lua error.
+#[derive(Debug, Clone, ToDoc)]
+#[doc_prefix = "Error while downloading a file:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub enum DownloadError {
+    #[doc_format(format_str = "The download of `{}' failed: {}.", _1, _2)]
+    TransferFailed(String, String),
+    #[doc_format(
+        format_str = "The download of `{}' failed with the HTTP status {}.",
+        _1,
+        _2
+    )]
+    HttpError(String, u32),
+    #[doc_format(
+        format_str = "The SHA-256 checksum of `{}' is {}, but {} was \
+                      expected.",
+        _1,
+        _2,
+        _3
+    )]
+    ChecksumMismatch(String, String, String),
+    #[doc_format(
+        format_str = "The download of `{}' could not be written to {}: {}.",
+        _1,
+        _2,
+        _3
+    )]
+    WriteFailed(String, String, String),
+    #[doc_format(
+        format_str = "`{}' is not in the download cache, and `--offline' \
+                      forbids downloading it.",
+        _1
+    )]
+    NotCached(String),
+    #[doc_format(format_str = "The download cache could not be used: {}.", _1)]
+    CacheFailed(String),
+}
+
+impl Display for DownloadError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.to_doc().render_fmt(80, f)
+    }
+}
+
+impl std::error::Error for DownloadError {}
+
+/// Options accepted by `curl` besides the URL.
+const OPTIONS: [&str; 7] = [
+    "url", "method", "headers", "timeout", "output", "sha256", "mode",
+];
+
+/// What to download with `curl` and where.
+struct CurlOptions {
+    url: String,
+    method: String,
+    headers: HashMap<String, String>,
+    /// Seconds after which the download is given up.
+    timeout: Option<f64>,
+    /// Where the body is written instead of being returned.
+    output: Option<PathBuf>,
+    /// Expected SHA-256 checksum of the body, in lowercase hexadecimal.
+    sha256: Option<String>,
+    /// Permissions of the output.
+    mode: Option<u32>,
+}
+
+impl CurlOptions {
+    fn new(url: String) -> CurlOptions {
+        CurlOptions {
+            url,
+            method: "GET".to_string(),
+            headers: HashMap::new(),
+            timeout: None,
+            output: None,
+            sha256: None,
+            mode: None,
+        }
+    }
+
+    /// Parses `curl{ "url", option = value, ... }`, the URL can also be given
+    /// as the `url` option.
+    fn from_table(table: Table) -> Result<CurlOptions, Error> {
+        manifest::check_options(&table, "curl", &OPTIONS, 1)?;
+
+        let url = match table.get::<_, Option<String>>("url")? {
+            Some(url) => url,
+            None => table
+                .get::<_, Option<String>>(1)?
+                .ok_or_else(|| Error::external("`curl' needs a `url'."))?,
+        };
+
+        let mut options = CurlOptions::new(url);
+        if let Some(method) = table.get::<_, Option<String>>("method")? {
+            options.method = method.to_uppercase();
+        }
+        options.headers =
+            table.get::<_, Option<_>>("headers")?.unwrap_or_default();
+        options.timeout = table.get("timeout")?;
+        options.output = table
+            .get::<_, Option<String>>("output")?
+            .map(|output| manifest::expand_home(&output));
+        options.sha256 = table
+            .get::<_, Option<String>>("sha256")?
+            .map(|sha256| sha256.to_lowercase());
+        if let Some(mode) = table.get::<_, Option<String>>("mode")? {
+            options.mode = Some(manifest::parse_mode(&mode)?);
+        }
+
+        if options.timeout.is_some_and(|timeout| timeout <= 0.0) {
+            return Err(Error::external(
+                "The `timeout' of `curl' must be positive.",
+            ));
+        }
+        if let Some(sha256) = &options.sha256 {
+            if sha256.len() != 64
+                || !sha256.chars().all(|c| c.is_ascii_hexdigit())
+            {
+                return Err(Error::external(format!(
+                    "Invalid `sha256' `{}', it must be 64 hexadecimal digits.",
+                    sha256
+                )));
+            }
+        }
+        if options.mode.is_some() && options.output.is_none() {
+            return Err(Error::external(
+                "The `mode' of `curl' needs an `output'.",
+            ));
+        }
+
+        Ok(options)
+    }
+}
+
+/// Downloads the URL of `options` to `sink` and returns the HTTP status, 0
+/// for the other protocols, and the SHA-256 checksum of the body.
+fn fetch(
+    options: &CurlOptions,
+    sink: &mut dyn Write,
+) -> Result<(u32, String), DownloadError> {
+    let url = &options.url;
+    let transfer_error = |err: curl::Error| {
+        DownloadError::TransferFailed(url.clone(), err.to_string())
+    };
+
+    let mut easy = Easy::new();
+    easy.url(url).map_err(transfer_error)?;
+    easy.follow_location(true).map_err(transfer_error)?;
+
+    match options.method.as_str() {
+        "GET" => (),
+        "HEAD" => easy.nobody(true).map_err(transfer_error)?,
+        method => easy.custom_request(method).map_err(transfer_error)?,
+    }
+
+    if !options.headers.is_empty() {
+        let mut headers = List::new();
+        for (name, value) in &options.headers {
+            headers
+                .append(&format!("{}: {}", name, value))
+                .map_err(transfer_error)?;
+        }
+        easy.http_headers(headers).map_err(transfer_error)?;
+    }
+
+    if let Some(timeout) = options.timeout {
+        easy.timeout(Duration::from_secs_f64(timeout))
+            .map_err(transfer_error)?;
+    }
+
+    let mut hasher = Sha256::new();
+    let mut write_error = None;
+    let result = {
+        let mut transfer = easy.transfer();
+        transfer
+            .write_function(|data| {
+                hasher.update(data);
+                match sink.write_all(data) {
+                    Ok(()) => Ok(data.len()),
+                    // Writing less than `data` aborts the transfer.
+                    Err(err) => {
+                        write_error = Some(err);
+                        Ok(0)
+                    }
+                }
+            })
+            .map_err(transfer_error)?;
+        transfer.perform()
+    };
+
+    if let Some(err) = write_error {
+        let output = options
+            .output
+            .as_deref()
+            .map(|output| output.display().to_string())
+            .unwrap_or_default();
+        return Err(DownloadError::WriteFailed(
+            url.clone(),
+            output,
+            err.to_string(),
+        ));
+    }
+    result.map_err(transfer_error)?;
+
+    let status = easy.response_code().map_err(transfer_error)?;
+    if status >= 400 {
+        return Err(DownloadError::HttpError(url.clone(), status));
+    }
+
+    let sha256 = format!("{:x}", hasher.finalize());
+    if let Some(expected) = &options.sha256 {
+        if &sha256 != expected {
+            return Err(DownloadError::ChecksumMismatch(
+                url.clone(),
+                sha256,
+                expected.clone(),
+            ));
+        }
+    }
+
+    Ok((status, sha256))
+}
+
+/// Writes `output` with `fill` through a temporary file next to it, which
+/// is only moved into place once `fill` succeeded.
+fn write_atomically<T>(
+    url: &str,
+    output: &Path,
+    mode: Option<u32>,
+    fill: impl FnOnce(&mut File) -> Result<T, DownloadError>,
+) -> Result<T, DownloadError> {
+    let write_error = |err: std::io::Error| {
+        DownloadError::WriteFailed(
+            url.to_string(),
+            output.display().to_string(),
+            err.to_string(),
+        )
+    };
+
+    if let Some(parent) = output.parent() {
+        std::fs::create_dir_all(parent).map_err(write_error)?;
+    }
+
+    let mut part_name = std::ffi::OsString::from(".");
+    part_name.push(output.file_name().unwrap_or_default());
+    part_name.push(".part");
+    let part = output.with_file_name(part_name);
+
+    let result =
+        File::create(&part)
+            .map_err(write_error)
+            .and_then(|mut file| {
+                let filled = fill(&mut file)?;
+                if let Some(mode) = mode {
+                    file.set_permissions(Permissions::from_mode(mode))
+                        .map_err(write_error)?;
+                }
+                std::fs::rename(&part, output).map_err(write_error)?;
+                Ok(filled)
+            });
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&part);
+    }
+
+    result
+}
+
+/// Returns the cached download of the URL of `options` whose checksum is
+/// `sha256`, downloading it first if needed, with the HTTP status if it was
+/// downloaded.
+fn cached_download(
+    cache: &DownloadCache,
+    options: &CurlOptions,
+    sha256: &str,
+) -> Result<(PathBuf, Option<u32>), DownloadError> {
+    let url = &options.url;
+    let cache_error =
+        |err: std::io::Error| DownloadError::CacheFailed(err.to_string());
 
-        easy.fail_on_error(true).unwrap();
-        easy.follow_location(true).unwrap();
+    if let Some(blob) = cache.get(url, sha256).map_err(cache_error)? {
+        return Ok((blob, None));
+    }
+    if cache.offline {
+        return Err(DownloadError::NotCached(url.clone()));
+    }
+
+    let blob = cache.blob_path(sha256);
+    let (status, _) =
+        write_atomically(url, &blob, None, |file| fetch(options, file))?;
+    cache.record(url, sha256).map_err(cache_error)?;
+
+    Ok((blob, Some(status)))
+}
 
-        easy.url(url.as_str()).unwrap();
+/// What `curl` downloaded.
+struct Download {
+    /// The HTTP status, there is none if the file was in the cache.
+    status: Option<u32>,
+    sha256: String,
+    /// The body, unless it was written to the output.
+    body: Option<Vec<u8>>,
+}
+
+/// Downloads the URL of `options` to its output or to memory. The `GET`
+/// downloads with a checksum go through `cache`, the body of another method
+/// may differ for the same URL.
+fn download(
+    cache: &DownloadCache,
+    options: &CurlOptions,
+) -> Result<Download, DownloadError> {
+    let url = &options.url;
+
+    let sha256 = match &options.sha256 {
+        Some(sha256) if options.method == "GET" => sha256,
+        _ if cache.offline => {
+            return Err(DownloadError::NotCached(url.clone()))
+        }
+        _ => {
+            return match &options.output {
+                Some(output) => {
+                    let (status, sha256) =
+                        write_atomically(url, output, options.mode, |file| {
+                            fetch(options, file)
+                        })?;
+                    Ok(Download {
+                        status: Some(status),
+                        sha256,
+                        body: None,
+                    })
+                }
+                None => {
+                    let mut body = Vec::new();
+                    let (status, sha256) = fetch(options, &mut body)?;
+                    Ok(Download {
+                        status: Some(status),
+                        sha256,
+                        body: Some(body),
+                    })
+                }
+            }
+        }
+    };
 
-        {
-            let mut transfer = easy.transfer();
+    let (blob, status) = cached_download(cache, options, sha256)?;
+    let cache_error =
+        |err: std::io::Error| DownloadError::CacheFailed(err.to_string());
 
-            transfer
-                .write_function(|data| {
-                    buf.extend_from_slice(data);
-                    Ok(data.len())
+    let body = match &options.output {
+        Some(output) => {
+            write_atomically(url, output, options.mode, |file| {
+                let mut cached = File::open(&blob).map_err(cache_error)?;
+                std::io::copy(&mut cached, file).map_err(|err| {
+                    DownloadError::WriteFailed(
+                        url.clone(),
+                        output.display().to_string(),
+                        err.to_string(),
+                    )
                 })
-                .unwrap();
+            })?;
+            None
+        }
+        None => Some(std::fs::read(&blob).map_err(cache_error)?),
+    };
+
+    Ok(Download {
+        status,
+        sha256: sha256.clone(),
+        body,
+    })
+}
+
+/// Downloads a URL, given alone or in a table with its options. Given alone,
+/// the body is returned. Otherwise a table is returned with the HTTP
+/// `status`, whether the file was `cached`, the `sha256` of the body and
+/// either the `body` or the `path` of the `output` it was written to.
+pub fn curl_fn(
+    lua: &Lua,
+    cache: DownloadCache,
+) -> Result<mlua::Function<'_>, CurlFunctionError> {
+    let fun = lua.create_function(move |lua, url: Value| {
+        let (options, body_only) = match url {
+            Value::String(url) => {
+                (CurlOptions::new(url.to_str()?.to_string()), true)
+            }
+            Value::Table(table) => (CurlOptions::from_table(table)?, false),
+            value => {
+                return Err(Error::external(format!(
+                    "`curl' expects a URL or a table, got a {}.",
+                    value.type_name()
+                )))
+            }
+        };
 
-            transfer.perform().unwrap();
+        let download = download(&cache, &options).map_err(Error::external)?;
+        let body = download
+            .body
+            .map(|body| lua.create_string(&body))
+            .transpose()?;
+
+        if body_only {
+            return Ok(body.map(Value::String).unwrap_or(Value::Nil));
         }
 
-        let str = String::from_utf8(buf).unwrap();
+        let result = lua.create_table()?;
+        result.set("status", download.status)?;
+        result.set("cached", download.status.is_none())?;
+        result.set("sha256", download.sha256)?;
+        result.set("body", body)?;
+        result.set(
+            "path",
+            options.output.as_deref().and_then(|output| output.to_str()),
+        )?;
 
-        Ok(str)
+        Ok(Value::Table(result))
     })?;
 
     Ok(fun)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        io::{BufRead, BufReader},
+        net::TcpListener,
+        os::unix::fs::PermissionsExt,
+        path::{Path, PathBuf},
+    };
+
+    use super::*;
+
+    /// The SHA-256 checksum of "hello\n".
+    const HELLO_SHA256: &str =
+        "5891b5b522d5df086d0ff0b110fbd9d21bb4fc7163af34d08286a2e846f6be03";
+
+    /// An empty directory for the test `name`.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "rdm-curl-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    /// Options to download `source` to `output`.
+    fn file_options(source: &Path, output: &Path) -> CurlOptions {
+        let mut options =
+            CurlOptions::new(format!("file://{}", source.display()));
+        options.output = Some(output.to_path_buf());
+        options
+    }
+
+    fn hello(dir: &Path) -> PathBuf {
+        let source = dir.join("source");
+        std::fs::write(&source, "hello\n").unwrap();
+        source
+    }
+
+    fn files(dir: &Path) -> Vec<String> {
+        let mut files: Vec<String> = std::fs::read_dir(dir)
+            .unwrap()
+            .map(|entry| entry.unwrap().file_name().into_string().unwrap())
+            .collect();
+        files.sort();
+        files
+    }
+
+    #[test]
+    fn download_with_matching_sha256() {
+        let dir = test_dir("matching");
+        let output = dir.join("out/hello");
+        let mut options = file_options(&hello(&dir), &output);
+        options.sha256 = Some(HELLO_SHA256.to_string());
+        let cache = DownloadCache::new(dir.join("cache"), false);
+
+        let first = download(&cache, &options).unwrap();
+
+        assert_eq!(first.sha256, HELLO_SHA256);
+        assert_eq!(first.status, Some(0));
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "hello\n");
+        assert_eq!(files(&dir.join("out")), ["hello"]);
+
+        // The second download comes from the cache.
+        let second = download(&cache, &options).unwrap();
+        assert_eq!(second.status, None);
+    }
+
+    #[test]
+    fn download_with_other_method_skips_cache() {
+        let dir = test_dir("method");
+        let output = dir.join("hello");
+        let mut options = file_options(&hello(&dir), &output);
+        options.sha256 = Some(HELLO_SHA256.to_string());
+        download(&DownloadCache::new(dir.join("cache"), false), &options)
+            .unwrap();
+
+        options.method = "POST".to_string();
+        let result =
+            download(&DownloadCache::new(dir.join("cache"), true), &options);
+
+        assert!(matches!(result, Err(DownloadError::NotCached(_))));
+    }
+
+    #[test]
+    fn download_with_mismatching_sha256() {
+        let dir = test_dir("mismatching");
+        let output = dir.join("hello");
+        std::fs::write(&output, "previous\n").unwrap();
+        let mut options = file_options(&hello(&dir), &output);
+        options.sha256 = Some("0".repeat(64));
+        let cache = DownloadCache::new(dir.join("cache"), false);
+
+        let result = download(&cache, &options);
+
+        assert!(matches!(result, Err(DownloadError::ChecksumMismatch(..))));
+        assert_eq!(std::fs::read_to_string(&output).unwrap(), "previous\n");
+        assert_eq!(files(&dir), ["cache", "hello", "source"]);
+        assert!(files(&dir.join("cache/blobs")).is_empty());
+    }
+
+    #[test]
+    fn download_applies_mode() {
+        let dir = test_dir("mode");
+        let output = dir.join("tool");
+        let mut options = file_options(&hello(&dir), &output);
+        options.mode = Some(0o751);
+        let cache = DownloadCache::new(dir.join("cache"), false);
+
+        let downloaded = download(&cache, &options).unwrap();
+
+        assert_eq!(downloaded.sha256, HELLO_SHA256);
+        assert!(downloaded.body.is_none());
+        let mode = std::fs::metadata(&output).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o751);
+    }
+
+    #[test]
+    fn download_fails_on_http_error() {
+        let dir = test_dir("http-error");
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            let mut line = String::new();
+            // Reads the request up to the empty line ending its headers.
+            while reader.read_line(&mut line).unwrap() > 0 {
+                if line == "\r\n" {
+                    break;
+                }
+                line.clear();
+            }
+            stream
+                .write_all(
+                    b"HTTP/1.1 404 Not Found\r\nContent-Length: 9\r\n\
+                      Connection: close\r\n\r\nnot found",
+                )
+                .unwrap();
+        });
+        let output = dir.join("missing");
+        let mut options =
+            CurlOptions::new(format!("http://127.0.0.1:{}/missing", port));
+        options.output = Some(output.clone());
+        let cache = DownloadCache::new(dir.join("cache"), false);
+
+        let result = download(&cache, &options);
+        server.join().unwrap();
+
+        assert!(matches!(result, Err(DownloadError::HttpError(_, 404))));
+        assert!(files(&dir).is_empty());
+    }
+}
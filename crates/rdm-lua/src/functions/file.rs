@@ -1,9 +1,10 @@
 use std::path::{Path, PathBuf};
 
-use git2::{Repository, Status};
-use mlua::{Function, Lua};
+use mlua::{Error, Function, Lua, Table, Value};
 use rdm_macros::{FromError, ToDoc};
 
+use crate::manifest::{self, FileEntry};
+
 #[derive(Debug, FromError, ToDoc)]
 #[doc_prefix = "Error while initializing the `file' function: "]
 #[doc_after_prefix = "pretty::RcDoc::line()"]
@@ -12,33 +13,67 @@ pub enum FileFunctionError {
     LuaError(mlua::Error),
 }
 
+/// Options accepted by `file` besides the path.
+const OPTIONS: [&str; 5] = ["mode", "hosts", "template", "target", "optional"];
+
+/// Builds the entry of `file{ "path", option = value, ... }`.
+fn parse_table(table: Table, worktree_path: &Path) -> Result<FileEntry, Error> {
+    manifest::check_options(&table, "file", &OPTIONS, 1)?;
+
+    let path: String = table.get(1)?;
+    let mut entry = FileEntry::new(manifest::resolve(&path, worktree_path)?);
+
+    entry.mode = match table.get::<_, Option<String>>("mode")? {
+        Some(mode) => Some(manifest::parse_mode(&mode)?),
+        None => None,
+    };
+    entry.hosts = table.get("hosts")?;
+    entry.template = table.get::<_, Option<bool>>("template")?.unwrap_or(false);
+    entry.target = match table.get::<_, Option<String>>("target")? {
+        Some(target) => Some(manifest::resolve(&target, worktree_path)?),
+        None => None,
+    };
+    entry.optional = table.get::<_, Option<bool>>("optional")?.unwrap_or(false);
+
+    // A template is never rendered over itself.
+    if entry.template
+        && entry.target.is_none()
+        && entry.path.extension().is_some_and(|ext| ext == "tmpl")
+    {
+        entry.target = Some(entry.path.with_extension(""));
+    }
+    if entry.template && entry.deployed_path() == entry.path {
+        return Err(Error::external(format!(
+            "The template {} needs another `target' or the `.tmpl' extension.",
+            path
+        )));
+    }
+
+    Ok(entry)
+}
+
+/// Declares a file of the configuration, either with its path or with a table
+/// holding its path and options.
 pub fn file_fn(
     lua: &Lua,
-    repo_path: PathBuf,
     worktree_path: PathBuf,
-) -> Result<Function, FileFunctionError> {
-    let file_fn = lua.create_function(move |_, file: String| {
-        let path = Path::new(&file);
-        let repo = Repository::open_bare(&repo_path).unwrap();
-        repo.set_workdir(&worktree_path, false).unwrap();
-
-        let status = repo.status_file(path).unwrap();
-        let mut index = repo.index().unwrap();
-
-        match status {
-            Status::WT_NEW
-            | Status::WT_DELETED
-            | Status::WT_RENAMED
-            | Status::WT_MODIFIED
-            | Status::WT_TYPECHANGE => {
-                log::info!("The file {} was added to your config.", file);
-                index.add_path(path).unwrap();
-                index.write().unwrap();
+) -> Result<Function<'_>, FileFunctionError> {
+    let file_fn = lua.create_function(move |lua, file: Value| {
+        let entry = match file {
+            Value::String(path) => FileEntry::new(manifest::resolve(
+                path.to_str()?,
+                &worktree_path,
+            )?),
+            Value::Table(table) => parse_table(table, &worktree_path)?,
+            value => {
+                return Err(Error::external(format!(
+                    "`file' expects a path or a table, got a {}.",
+                    value.type_name()
+                )))
             }
-            _ => (),
         };
 
-        Ok(())
+        manifest::with_manifest(lua, |manifest| manifest.files.push(entry))
     })?;
 
     Ok(file_fn)
@@ -0,0 +1,30 @@
+use mlua::{Error, Function, Lua};
+use rdm_macros::{FromError, ToDoc};
+
+use crate::manifest;
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "Error while initializing the `groups' function: "]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub enum GroupsFunctionError {
+    #[doc_to_string]
+    LuaError(mlua::Error),
+}
+
+/// Declares the groups of the host.
+pub fn groups_fn(lua: &Lua) -> Result<Function<'_>, GroupsFunctionError> {
+    let groups_fn = lua.create_function(|lua, groups: Vec<String>| {
+        if let Some(group) =
+            groups.iter().find(|g| g.is_empty() || g.contains(','))
+        {
+            return Err(Error::external(format!(
+                "Invalid group name: `{}'.",
+                group
+            )));
+        }
+
+        manifest::with_manifest(lua, |manifest| manifest.groups = Some(groups))
+    })?;
+
+    Ok(groups_fn)
+}
@@ -1,9 +1,19 @@
+pub mod copy;
 pub mod curl;
 pub mod directory;
 pub mod file;
+pub mod groups;
 pub mod run_script;
+pub mod secret_file;
+pub mod symlink;
+pub mod variables;
 
+pub use self::copy::copy_fn;
 pub use self::curl::curl_fn;
 pub use self::directory::directory_fn;
 pub use self::file::file_fn;
+pub use self::groups::groups_fn;
 pub use self::run_script::run_script_fn;
+pub use self::secret_file::secret_file_fn;
+pub use self::symlink::symlink_fn;
+pub use self::variables::variables_fn;
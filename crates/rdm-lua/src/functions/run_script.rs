@@ -1,30 +1,535 @@
-use mlua::Lua;
+use std::{
+    collections::HashMap,
+    fmt::{self, Display},
+    io::Read,
+    os::unix::process::CommandExt,
+    process::{Child, Command, ExitStatus, Stdio},
+    sync::mpsc::{self, Receiver, RecvTimeoutError},
+    thread,
+    time::{Duration, Instant},
+};
+
+use mlua::{Error, Lua, Table, Value};
 use rdm_macros::{FromError, ToDoc};
-use run_script::ScriptOptions;
+
+use crate::manifest;
 
 #[derive(Debug, FromError, ToDoc)]
-#[doc_prefix = "Error while initializing the `curl' function: "]
+#[doc_prefix = "Error while initializing the `run_script' function: "]
 #[doc_after_prefix = "pretty::RcDoc::line()"]
 pub enum RunScriptFunctionError {
     #[doc_to_string]
     LuaError(mlua::Error),
 }
 
+/// Why a script run by `run_script` failed, raised as a Lua error.
+#[derive(Debug, Clone, ToDoc)]
+#[doc_prefix = "Error while running a script:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub enum ScriptError {
+    #[doc_format(
+        format_str = "The shell `{}' could not be started: {}.",
+        _1,
+        _2
+    )]
+    SpawnFailed(String, String),
+    #[doc_format(
+        format_str = "The directory `{}' to run the script in does not exist.",
+        _1
+    )]
+    CwdNotFound(String),
+    #[doc_format(
+        format_str = "The script `{}' exited with the code {}.",
+        _1,
+        _2
+    )]
+    Failed(String, i32),
+    #[doc_format(
+        format_str = "The script `{}' exited with the code {}:\n{}",
+        _1,
+        _2,
+        _3
+    )]
+    FailedWithOutput(String, i32, String),
+    #[doc_format(format_str = "The script `{}' was killed by a signal.", _1)]
+    Killed(String),
+    #[doc_format(
+        format_str = "The script `{}' did not finish in {} seconds and was \
+                      killed.",
+        _1,
+        _2
+    )]
+    TimedOut(String, f64),
+    #[doc_format(
+        format_str = "The script `{}' could not be waited for: {}.",
+        _1,
+        _2
+    )]
+    WaitFailed(String, String),
+}
+
+impl Display for ScriptError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.to_doc().render_fmt(80, f)
+    }
+}
+
+impl std::error::Error for ScriptError {}
+
+/// Options accepted by `run_script` besides the script.
+const OPTIONS: [&str; 8] = [
+    "script", "shell", "args", "env", "cwd", "capture", "timeout", "check",
+];
+
+/// How to run a script, as given to `run_script`.
+struct ScriptOptions {
+    script: String,
+    shell: String,
+    args: Vec<String>,
+    env: HashMap<String, String>,
+    cwd: Option<String>,
+    /// Whether the output is returned instead of being printed.
+    capture: bool,
+    /// Seconds after which the script is killed.
+    timeout: Option<f64>,
+    /// Whether a failure raises an error.
+    check: bool,
+}
+
+impl ScriptOptions {
+    fn new(script: String) -> ScriptOptions {
+        ScriptOptions {
+            script,
+            shell: "bash".to_string(),
+            args: Vec::new(),
+            env: HashMap::new(),
+            cwd: None,
+            capture: false,
+            timeout: None,
+            check: true,
+        }
+    }
+
+    /// Parses `run_script{ "script", option = value, ... }`, the script can
+    /// also be given as the `script` option.
+    fn from_table(table: Table) -> Result<ScriptOptions, Error> {
+        manifest::check_options(&table, "run_script", &OPTIONS, 1)?;
+
+        let script = match table.get::<_, Option<String>>("script")? {
+            Some(script) => script,
+            None => table.get::<_, Option<String>>(1)?.ok_or_else(|| {
+                Error::external("`run_script' needs a `script'.")
+            })?,
+        };
+
+        let mut options = ScriptOptions::new(script);
+        if let Some(shell) = table.get("shell")? {
+            options.shell = shell;
+        }
+        options.args = table.get::<_, Option<_>>("args")?.unwrap_or_default();
+        options.env = table.get::<_, Option<_>>("env")?.unwrap_or_default();
+        options.cwd = table.get("cwd")?;
+        options.capture =
+            table.get::<_, Option<_>>("capture")?.unwrap_or(false);
+        options.timeout = table.get("timeout")?;
+        options.check = table.get::<_, Option<_>>("check")?.unwrap_or(true);
+
+        if options.timeout.is_some_and(|timeout| timeout <= 0.0) {
+            return Err(Error::external(
+                "The `timeout' of `run_script' must be positive.",
+            ));
+        }
+
+        Ok(options)
+    }
+
+    /// A short name of the script for the errors, its first line.
+    fn name(&self) -> String {
+        let line = self.script.trim().lines().next().unwrap_or_default();
+
+        if line.chars().count() > 40 {
+            format!("{}...", line.chars().take(40).collect::<String>())
+        } else {
+            line.to_string()
+        }
+    }
+}
+
+/// Reads `pipe` to its end on another thread, so that a script filling both
+/// its outputs does not block. The output is sent once the pipe is closed.
+fn read_pipe<R: Read + Send + 'static>(
+    pipe: Option<R>,
+) -> Option<Receiver<Vec<u8>>> {
+    pipe.map(|mut pipe| {
+        let (sender, receiver) = mpsc::channel();
+        thread::spawn(move || {
+            let mut buf = Vec::new();
+            let _ = pipe.read_to_end(&mut buf);
+            let _ = sender.send(buf);
+        });
+        receiver
+    })
+}
+
+/// The output read by `reader`, or an error if it is not closed by
+/// `deadline`. Without a deadline, a process started in the background by the
+/// script that keeps the output open makes it wait until that process ends.
+fn joined_output(
+    reader: Option<Receiver<Vec<u8>>>,
+    deadline: Option<Instant>,
+) -> Result<Option<String>, RecvTimeoutError> {
+    let reader = match reader {
+        Some(reader) => reader,
+        None => return Ok(None),
+    };
+
+    let buf = match deadline {
+        Some(deadline) => reader
+            .recv_timeout(deadline.saturating_duration_since(Instant::now()))?,
+        None => reader.recv().unwrap_or_default(),
+    };
+
+    Ok(Some(String::from_utf8_lossy(&buf).to_string()))
+}
+
+/// Kills the script run by `child` with the processes it started, they share
+/// the process group of the shell.
+fn kill_group(child: &mut Child) {
+    unsafe {
+        libc::kill(-(child.id() as libc::pid_t), libc::SIGKILL);
+    }
+    let _ = child.wait();
+}
+
+/// How a script run by `run` ended.
+struct ScriptRun {
+    /// The exit status, there is none if the script timed out.
+    status: Option<ExitStatus>,
+    stdout: Option<String>,
+    stderr: Option<String>,
+}
+
+/// Runs the script of `options` with `<shell> -c <script> <shell> <args>...`,
+/// so that the arguments are `$1`, `$2`...
+fn run(options: &ScriptOptions) -> Result<ScriptRun, ScriptError> {
+    let mut command = Command::new(&options.shell);
+    command
+        .arg("-c")
+        .arg(&options.script)
+        .arg(&options.shell)
+        .args(&options.args)
+        .envs(&options.env);
+
+    // With a timeout, the script and the processes it starts are killed
+    // together. Otherwise the script stays in the foreground group of the
+    // terminal, to be able to read from it.
+    if options.timeout.is_some() {
+        command.process_group(0);
+    }
+
+    if let Some(cwd) = &options.cwd {
+        // Otherwise `spawn` fails as if the shell was missing.
+        let cwd = manifest::expand_home(cwd);
+        if !cwd.is_dir() {
+            return Err(ScriptError::CwdNotFound(cwd.display().to_string()));
+        }
+        command.current_dir(cwd);
+    }
+    if options.capture {
+        command.stdout(Stdio::piped()).stderr(Stdio::piped());
+    }
+
+    let mut child = command.spawn().map_err(|err| {
+        ScriptError::SpawnFailed(options.shell.clone(), err.to_string())
+    })?;
+    let stdout = read_pipe(child.stdout.take());
+    let stderr = read_pipe(child.stderr.take());
+
+    let wait_error = |err: std::io::Error| {
+        ScriptError::WaitFailed(options.name(), err.to_string())
+    };
+
+    let deadline = options
+        .timeout
+        .map(|timeout| Instant::now() + Duration::from_secs_f64(timeout));
+    let timed_out = ScriptRun {
+        status: None,
+        stdout: None,
+        stderr: None,
+    };
+
+    let status = match deadline {
+        None => child.wait().map_err(wait_error)?,
+        Some(deadline) => loop {
+            if let Some(status) = child.try_wait().map_err(wait_error)? {
+                break status;
+            }
+            if Instant::now() >= deadline {
+                // The readers are left behind, the processes holding their
+                // pipes may not be gone yet.
+                kill_group(&mut child);
+                return Ok(timed_out);
+            }
+            thread::sleep(Duration::from_millis(10));
+        },
+    };
+
+    // The script may be done while the processes it started still hold its
+    // outputs, they are killed at the deadline.
+    let (stdout, stderr) = match (
+        joined_output(stdout, deadline),
+        joined_output(stderr, deadline),
+    ) {
+        (Ok(stdout), Ok(stderr)) => (stdout, stderr),
+        _ => {
+            kill_group(&mut child);
+            return Ok(timed_out);
+        }
+    };
+
+    Ok(ScriptRun {
+        status: Some(status),
+        stdout,
+        stderr,
+    })
+}
+
+/// Runs a script, given alone or in a table with its options, and returns a
+/// table with its exit `code`, whether it was a `success`, whether it
+/// `timed_out` and, if captured, its `stdout` and `stderr`. A failure or a
+/// timeout raises an error unless `check` is `false`.
 pub fn run_script_fn(
     lua: &Lua,
-) -> Result<mlua::Function, RunScriptFunctionError> {
-    let run_fn = lua.create_function(|_, script: String| {
-        let mut options = ScriptOptions::new();
-        options.runner = Some("/bin/bash".to_string());
-        options.output_redirection = run_script::IoOptions::Inherit;
+) -> Result<mlua::Function<'_>, RunScriptFunctionError> {
+    let run_fn = lua.create_function(|lua, script: Value| {
+        let options = match script {
+            Value::String(script) => {
+                ScriptOptions::new(script.to_str()?.to_string())
+            }
+            Value::Table(table) => ScriptOptions::from_table(table)?,
+            value => {
+                return Err(Error::external(format!(
+                    "`run_script' expects a script or a table, got a {}.",
+                    value.type_name()
+                )))
+            }
+        };
+
+        let ScriptRun {
+            status,
+            stdout,
+            stderr,
+        } = run(&options).map_err(Error::external)?;
+
+        let result = lua.create_table()?;
+        let status = match status {
+            Some(status) => status,
+            None if options.check => {
+                return Err(Error::external(ScriptError::TimedOut(
+                    options.name(),
+                    options.timeout.unwrap_or_default(),
+                )))
+            }
+            None => {
+                result.set("success", false)?;
+                result.set("timed_out", true)?;
+                return Ok(result);
+            }
+        };
 
-        let args = vec![];
+        if options.check && !status.success() {
+            let name = options.name();
+            let err = match (status.code(), &stderr) {
+                (None, _) => ScriptError::Killed(name),
+                (Some(code), Some(stderr)) if !stderr.trim().is_empty() => {
+                    ScriptError::FailedWithOutput(
+                        name,
+                        code,
+                        stderr.trim_end().to_string(),
+                    )
+                }
+                (Some(code), _) => ScriptError::Failed(name, code),
+            };
+            return Err(Error::external(err));
+        }
 
-        let (code, output, error) =
-            run_script::run(script.as_str(), &args, &options).unwrap();
+        result.set("code", status.code())?;
+        result.set("success", status.success())?;
+        result.set("timed_out", false)?;
+        result.set("stdout", stdout)?;
+        result.set("stderr", stderr)?;
 
-        Ok((code, output, error))
+        Ok(result)
     })?;
 
     Ok(run_fn)
 }
+
+#[cfg(test)]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::*;
+    use crate::RuntimeError;
+
+    fn test_lua() -> Lua {
+        let lua = Lua::new();
+        lua.globals()
+            .set("run_script", run_script_fn(&lua).unwrap())
+            .unwrap();
+        lua
+    }
+
+    /// The error raised by `source`, which must fail.
+    fn script_error(source: &str) -> ScriptError {
+        let err = test_lua().load(source).exec().unwrap_err();
+
+        match RuntimeError::from_lua(err) {
+            RuntimeError::ScriptError(err) => err,
+            err => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    /// An empty directory for the test `name`.
+    fn test_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!(
+            "rdm-run-script-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        std::fs::create_dir_all(&dir).unwrap();
+        std::fs::canonicalize(dir).unwrap()
+    }
+
+    #[test]
+    fn default_shell_is_bash() {
+        assert_eq!(ScriptOptions::new("true".to_string()).shell, "bash");
+    }
+
+    #[test]
+    fn args_are_positional_parameters() {
+        let lua = test_lua();
+        let stdout: String = lua
+            .load(
+                r#"return run_script{
+                    "echo $1-$2", shell = "sh", args = {"a", "b c"},
+                    capture = true,
+                }.stdout"#,
+            )
+            .eval()
+            .unwrap();
+
+        assert_eq!(stdout, "a-b c\n");
+    }
+
+    #[test]
+    fn env_and_cwd_are_applied() {
+        let dir = test_dir("cwd");
+        let lua = test_lua();
+        lua.globals().set("dir", dir.to_str().unwrap()).unwrap();
+        let stdout: String = lua
+            .load(
+                r#"return run_script{
+                    "echo $RDM_TEST; pwd", shell = "sh",
+                    env = {RDM_TEST = "value"}, cwd = dir, capture = true,
+                }.stdout"#,
+            )
+            .eval()
+            .unwrap();
+
+        assert_eq!(stdout, format!("value\n{}\n", dir.display()));
+    }
+
+    #[test]
+    fn missing_cwd_is_reported() {
+        let err = script_error(
+            r#"run_script{"true", shell = "sh", cwd = "/nonexistent-rdm"}"#,
+        );
+
+        match err {
+            ScriptError::CwdNotFound(dir) => {
+                assert_eq!(dir, "/nonexistent-rdm")
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn capture_returns_outputs() {
+        let lua = test_lua();
+        let result: Table = lua
+            .load(
+                r#"return run_script{
+                    "echo out; echo err >&2", shell = "sh", capture = true,
+                }"#,
+            )
+            .eval()
+            .unwrap();
+
+        assert_eq!(result.get::<_, String>("stdout").unwrap(), "out\n");
+        assert_eq!(result.get::<_, String>("stderr").unwrap(), "err\n");
+        assert_eq!(result.get::<_, i32>("code").unwrap(), 0);
+        assert!(result.get::<_, bool>("success").unwrap());
+    }
+
+    #[test]
+    fn unchecked_failure_is_returned() {
+        let lua = test_lua();
+        let result: Table = lua
+            .load(r#"return run_script{"exit 3", shell = "sh", check = false}"#)
+            .eval()
+            .unwrap();
+
+        assert_eq!(result.get::<_, i32>("code").unwrap(), 3);
+        assert!(!result.get::<_, bool>("success").unwrap());
+        assert!(!result.get::<_, bool>("timed_out").unwrap());
+    }
+
+    #[test]
+    fn failure_raises_error_with_output() {
+        let err = script_error(
+            r#"run_script{
+                "echo broken >&2; exit 4", shell = "sh", capture = true,
+            }"#,
+        );
+
+        match err {
+            ScriptError::FailedWithOutput(name, code, stderr) => {
+                assert_eq!(name, "echo broken >&2; exit 4");
+                assert_eq!(code, 4);
+                assert_eq!(stderr, "broken");
+            }
+            err => panic!("unexpected error: {:?}", err),
+        }
+    }
+
+    #[test]
+    fn timeout_kills_script() {
+        let start = Instant::now();
+        let err = script_error(
+            r#"run_script{"sleep 10", shell = "sh", timeout = 0.2}"#,
+        );
+
+        assert!(
+            matches!(err, ScriptError::TimedOut(_, timeout) if timeout == 0.2)
+        );
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let lua = test_lua();
+        let result: Table = lua
+            .load(
+                r#"return run_script{
+                    "sleep 10 & echo started", shell = "sh", capture = true,
+                    timeout = 0.2, check = false,
+                }"#,
+            )
+            .eval()
+            .unwrap();
+
+        assert!(result.get::<_, bool>("timed_out").unwrap());
+        assert!(!result.get::<_, bool>("success").unwrap());
+        assert_eq!(result.get::<_, Option<i32>>("code").unwrap(), None);
+        assert!(start.elapsed() < Duration::from_secs(5));
+    }
+}
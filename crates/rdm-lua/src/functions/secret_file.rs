@@ -0,0 +1,77 @@
+use std::path::{Path, PathBuf};
+
+use mlua::{Error, Function, Lua, Table, Value};
+use rdm_macros::{FromError, ToDoc};
+
+use crate::manifest::{self, FileEntry};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "Error while initializing the `secret_file' function: "]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub enum SecretFileFunctionError {
+    #[doc_to_string]
+    LuaError(mlua::Error),
+}
+
+/// Options accepted by `secret_file` besides the path.
+const OPTIONS: [&str; 3] = ["mode", "hosts", "optional"];
+
+/// Permissions of the decrypted files, unless given.
+const DEFAULT_MODE: u32 = 0o600;
+
+/// Builds the entry of the secret file deployed at `path`, it is saved
+/// encrypted next to it with the `.secret` extension.
+fn secret_entry(path: &str, worktree_path: &Path) -> Result<FileEntry, Error> {
+    let target = manifest::resolve(path, worktree_path)?;
+    let mut source = target.clone().into_os_string();
+    source.push(".secret");
+
+    let mut entry = FileEntry::new(PathBuf::from(source));
+    entry.target = Some(target);
+    entry.mode = Some(DEFAULT_MODE);
+    entry.secret = true;
+
+    Ok(entry)
+}
+
+/// Builds the entry of `secret_file{ "path", option = value, ... }`.
+fn parse_table(table: Table, worktree_path: &Path) -> Result<FileEntry, Error> {
+    manifest::check_options(&table, "secret_file", &OPTIONS, 1)?;
+
+    let path: String = table.get(1)?;
+    let mut entry = secret_entry(&path, worktree_path)?;
+
+    if let Some(mode) = table.get::<_, Option<String>>("mode")? {
+        entry.mode = Some(manifest::parse_mode(&mode)?);
+    }
+    entry.hosts = table.get("hosts")?;
+    entry.optional = table.get::<_, Option<bool>>("optional")?.unwrap_or(false);
+
+    Ok(entry)
+}
+
+/// Declares a file whose content is saved encrypted, either with its path or
+/// with a table holding its path and options.
+pub fn secret_file_fn(
+    lua: &Lua,
+    worktree_path: PathBuf,
+) -> Result<Function<'_>, SecretFileFunctionError> {
+    let secret_file_fn = lua.create_function(move |lua, file: Value| {
+        let entry = match file {
+            Value::String(path) => {
+                secret_entry(path.to_str()?, &worktree_path)?
+            }
+            Value::Table(table) => parse_table(table, &worktree_path)?,
+            value => {
+                return Err(Error::external(format!(
+                    "`secret_file' expects a path or a table, got a {}.",
+                    value.type_name()
+                )))
+            }
+        };
+
+        manifest::with_manifest(lua, |manifest| manifest.files.push(entry))
+    })?;
+
+    Ok(secret_file_fn)
+}
@@ -0,0 +1,56 @@
+use std::path::PathBuf;
+
+use mlua::{Error, Function, Lua, Table};
+use rdm_macros::{FromError, ToDoc};
+
+use crate::manifest::{self, LinkEntry};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "Error while initializing the `symlink' function: "]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub enum SymlinkFunctionError {
+    #[doc_to_string]
+    LuaError(mlua::Error),
+}
+
+/// Options accepted by `symlink`.
+const OPTIONS: [&str; 3] = ["source", "target", "force"];
+
+/// Declares a link created at `target` to `source`, a file or directory of
+/// the worktree: `symlink{ source = "nvim", target = "~/.config/nvim" }`.
+pub fn symlink_fn(
+    lua: &Lua,
+    worktree_path: PathBuf,
+) -> Result<Function<'_>, SymlinkFunctionError> {
+    let symlink_fn = lua.create_function(move |lua, table: Table| {
+        manifest::check_options(&table, "symlink", &OPTIONS, 0)?;
+
+        let (source, target) = match (
+            table.get::<_, Option<String>>("source")?,
+            table.get::<_, Option<String>>("target")?,
+        ) {
+            (Some(source), Some(target)) => (source, target),
+            _ => {
+                return Err(Error::external(
+                    "`symlink' needs a `source' and a `target'.",
+                ))
+            }
+        };
+
+        let entry = LinkEntry {
+            source: manifest::resolve(&source, &worktree_path)?,
+            target: manifest::resolve(&target, &worktree_path)?,
+            force: table.get::<_, Option<bool>>("force")?.unwrap_or(false),
+        };
+        if entry.source == entry.target {
+            return Err(Error::external(format!(
+                "The link {} can not point to itself.",
+                target
+            )));
+        }
+
+        manifest::with_manifest(lua, |manifest| manifest.links.push(entry))
+    })?;
+
+    Ok(symlink_fn)
+}
@@ -0,0 +1,69 @@
+use std::collections::BTreeMap;
+
+use mlua::{Error, Function, Lua, Table, Value};
+use rdm_macros::{FromError, ToDoc};
+
+use crate::manifest;
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "Error while initializing the `variables' function: "]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub enum VariablesFunctionError {
+    #[doc_to_string]
+    LuaError(mlua::Error),
+}
+
+/// Converts a Lua value into a template value, the tables with a sequence
+/// become lists and the other ones maps.
+fn to_template_value(value: Value) -> Result<minijinja::Value, Error> {
+    Ok(match value {
+        Value::Nil => minijinja::Value::from(()),
+        Value::Boolean(b) => minijinja::Value::from(b),
+        Value::Integer(i) => minijinja::Value::from(i),
+        Value::Number(n) => minijinja::Value::from(n),
+        Value::String(s) => minijinja::Value::from(s.to_str()?),
+        Value::Table(table) if table.raw_len() > 0 => table
+            .sequence_values::<Value>()
+            .map(|value| to_template_value(value?))
+            .collect::<Result<Vec<_>, Error>>()?
+            .into(),
+        Value::Table(table) => table
+            .pairs::<String, Value>()
+            .map(|pair| {
+                let (key, value) = pair?;
+                Ok((key, to_template_value(value)?))
+            })
+            .collect::<Result<BTreeMap<_, _>, Error>>()?
+            .into(),
+        value => {
+            return Err(Error::external(format!(
+                "A {} cannot be used in a template.",
+                value.type_name()
+            )))
+        }
+    })
+}
+
+/// Declares variables of the templates, the variables of several calls are
+/// merged.
+pub fn variables_fn(lua: &Lua) -> Result<Function<'_>, VariablesFunctionError> {
+    let variables_fn = lua.create_function(|lua, variables: Table| {
+        let mut values = BTreeMap::new();
+
+        for pair in variables.pairs::<String, Value>() {
+            let (name, value) = pair?;
+            if name == "facts" {
+                return Err(Error::external(
+                    "The variable `facts' is reserved for `rdm.facts'.",
+                ));
+            }
+            values.insert(name, to_template_value(value)?);
+        }
+
+        manifest::with_manifest(lua, |manifest| {
+            manifest.variables.extend(values)
+        })
+    })?;
+
+    Ok(variables_fn)
+}
@@ -1,5 +1,14 @@
 #![allow(clippy::enum_variant_names)]
+mod cache;
+mod facts;
 mod functions;
+mod manifest;
+mod template;
+
+pub use cache::{CacheEntry, DownloadCache};
+pub use facts::Facts;
+pub use manifest::{evaluate, DirectoryEntry, FileEntry, LinkEntry, Manifest};
+pub use template::{render, TemplateError};
 
 use std::path::PathBuf;
 
@@ -13,28 +22,85 @@ pub enum RuntimeError {
     RunScriptFunctionError(functions::run_script::RunScriptFunctionError),
     CurlFunctionError(functions::curl::CurlFunctionError),
     FileFunctionError(functions::file::FileFunctionError),
+    SecretFileFunctionError(functions::secret_file::SecretFileFunctionError),
+    SymlinkFunctionError(functions::symlink::SymlinkFunctionError),
+    CopyFunctionError(functions::copy::CopyFunctionError),
     DirectoryFunctionError(functions::directory::DirectoryFunctionError),
+    GroupsFunctionError(functions::groups::GroupsFunctionError),
+    VariablesFunctionError(functions::variables::VariablesFunctionError),
+    ScriptError(functions::run_script::ScriptError),
+    DownloadError(functions::curl::DownloadError),
     #[doc_to_string]
     LuaError(mlua::Error),
 }
 
+/// The error of a Rust function, such as `run_script`, that caused `err`, if
+/// any.
+fn external_error(
+    err: &mlua::Error,
+) -> Option<&(dyn std::error::Error + Send + Sync + 'static)> {
+    match err {
+        mlua::Error::CallbackError { cause, .. } => external_error(cause),
+        mlua::Error::ExternalError(err) => Some(err.as_ref()),
+        _ => None,
+    }
+}
+
+impl RuntimeError {
+    /// Converts an error raised while running Lua code, keeping the errors of
+    /// the scripts run by `run_script` and of the downloads made by `curl`.
+    pub fn from_lua(err: mlua::Error) -> RuntimeError {
+        let external = external_error(&err);
+
+        if let Some(err) = external.and_then(|err| {
+            err.downcast_ref::<functions::run_script::ScriptError>()
+        }) {
+            return RuntimeError::ScriptError(err.clone());
+        }
+        if let Some(err) = external.and_then(|err| {
+            err.downcast_ref::<functions::curl::DownloadError>()
+        }) {
+            return RuntimeError::DownloadError(err.clone());
+        }
+
+        RuntimeError::LuaError(err)
+    }
+}
+
 pub fn init(
-    repo_path: PathBuf,
     worktree_path: PathBuf,
+    facts: Facts,
+    cache: DownloadCache,
 ) -> Result<Lua, RuntimeError> {
     let lua = Lua::new();
 
+    let rdm = lua.create_table()?;
+    rdm.set("facts", facts.to_lua(&lua)?)?;
+    lua.globals().set("rdm", rdm)?;
+
     lua.globals()
         .set("run_script", functions::run_script_fn(&lua)?)?;
-    lua.globals().set("curl", functions::curl_fn(&lua)?)?;
+    lua.globals()
+        .set("curl", functions::curl_fn(&lua, cache)?)?;
+    lua.globals()
+        .set("file", functions::file_fn(&lua, worktree_path.clone())?)?;
     lua.globals().set(
-        "file",
-        functions::file_fn(&lua, repo_path.clone(), worktree_path.clone())?,
+        "secret_file",
+        functions::secret_file_fn(&lua, worktree_path.clone())?,
     )?;
     lua.globals().set(
         "directory",
-        functions::directory_fn(&lua, repo_path, worktree_path)?,
+        functions::directory_fn(&lua, worktree_path.clone())?,
     )?;
+    lua.globals().set(
+        "symlink",
+        functions::symlink_fn(&lua, worktree_path.clone())?,
+    )?;
+    lua.globals()
+        .set("copy", functions::copy_fn(&lua, worktree_path)?)?;
+    lua.globals().set("groups", functions::groups_fn(&lua)?)?;
+    lua.globals()
+        .set("variables", functions::variables_fn(&lua)?)?;
 
     Ok(lua)
 }
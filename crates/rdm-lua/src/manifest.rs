@@ -0,0 +1,276 @@
+use std::{
+    collections::BTreeMap,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use ignore::{gitignore::GitignoreBuilder, WalkBuilder};
+use mlua::{Error, Lua, Table, Value};
+
+/// A file declared with the `file` function.
+#[derive(Debug, Clone)]
+pub struct FileEntry {
+    /// Path of the file, relative to the worktree.
+    pub path: PathBuf,
+    /// Permissions enforced on the deployed file.
+    pub mode: Option<u32>,
+    /// The hosts the file is restricted to, every host if `None`.
+    pub hosts: Option<Vec<String>>,
+    /// Whether the deployed file is rendered from the file as a template.
+    pub template: bool,
+    /// Where the file is deployed, relative to the worktree, if not at `path`.
+    /// The target of a template is its path without the `.tmpl` extension
+    /// unless given, the target of a copy is absolute.
+    pub target: Option<PathBuf>,
+    /// Whether a missing file is skipped silently.
+    pub optional: bool,
+    /// Whether the file is saved encrypted and deployed decrypted to its
+    /// target, see `secret_file`.
+    pub secret: bool,
+}
+
+impl FileEntry {
+    pub fn new(path: PathBuf) -> FileEntry {
+        FileEntry {
+            path,
+            mode: None,
+            hosts: None,
+            template: false,
+            target: None,
+            optional: false,
+            secret: false,
+        }
+    }
+
+    /// Path of the deployed file, relative to the worktree.
+    pub fn deployed_path(&self) -> &Path {
+        self.target.as_deref().unwrap_or(&self.path)
+    }
+
+    /// Whether the file is deployed somewhere else than where it is saved, or
+    /// with another content.
+    pub fn is_deployed(&self) -> bool {
+        self.template || self.secret || self.target.is_some()
+    }
+
+    /// Whether the file is part of the configuration of `host`.
+    pub fn applies_to(&self, host: &str) -> bool {
+        match &self.hosts {
+            Some(hosts) => hosts.iter().any(|h| h == host),
+            None => true,
+        }
+    }
+}
+
+/// A directory declared with the `directory` function.
+#[derive(Debug, Clone)]
+pub struct DirectoryEntry {
+    /// Path of the directory, relative to the worktree.
+    pub path: PathBuf,
+    /// Gitignore patterns of the files to leave out, relative to the
+    /// directory.
+    pub ignore: Vec<String>,
+    /// Whether the `.gitignore` files inside the directory are applied.
+    pub respect_gitignore: bool,
+    /// How deep the files are looked for, the files of the directory itself
+    /// being at the depth `1`.
+    pub max_depth: Option<usize>,
+    /// Whether the symbolic links to directories are walked into, instead of
+    /// being added as links.
+    pub follow_symlinks: bool,
+}
+
+impl DirectoryEntry {
+    pub fn new(path: PathBuf) -> DirectoryEntry {
+        DirectoryEntry {
+            path,
+            ignore: Vec::new(),
+            respect_gitignore: false,
+            max_depth: None,
+            follow_symlinks: false,
+        }
+    }
+
+    /// Lists the files of the directory, relative to the worktree. The `.git`
+    /// directories and the files that are neither regular files nor links,
+    /// such as sockets, are always left out.
+    pub fn files(&self, worktree_path: &Path) -> std::io::Result<Vec<PathBuf>> {
+        let root = worktree_path.join(&self.path);
+
+        let mut ignore = GitignoreBuilder::new(&root);
+        for pattern in self.ignore.iter() {
+            ignore
+                .add_line(None, pattern)
+                .map_err(std::io::Error::other)?;
+        }
+        let ignore = ignore.build().map_err(std::io::Error::other)?;
+
+        let walk = WalkBuilder::new(&root)
+            .standard_filters(false)
+            .git_ignore(self.respect_gitignore)
+            .require_git(false)
+            .max_depth(self.max_depth)
+            .follow_links(self.follow_symlinks)
+            .filter_entry(move |entry| {
+                let is_dir = entry.file_type().is_some_and(|t| t.is_dir());
+                let skipped = (is_dir && entry.file_name() == ".git")
+                    || ignore.matched(entry.path(), is_dir).is_ignore();
+                entry.depth() == 0 || !skipped
+            })
+            .build();
+
+        let mut files = Vec::new();
+        for entry in walk {
+            let entry = entry.map_err(std::io::Error::other)?;
+            match entry.file_type() {
+                Some(t) if t.is_file() || t.is_symlink() => {
+                    let path =
+                        entry.path().strip_prefix(worktree_path).unwrap();
+                    files.push(path.to_path_buf());
+                }
+                _ => (),
+            }
+        }
+        files.sort();
+
+        Ok(files)
+    }
+}
+
+/// A symbolic link declared with the `symlink` function.
+#[derive(Debug, Clone)]
+pub struct LinkEntry {
+    /// The file or directory linked to, relative to the worktree.
+    pub source: PathBuf,
+    /// Where the link is created, relative to the worktree.
+    pub target: PathBuf,
+    /// Whether a file or another link at the target is replaced.
+    pub force: bool,
+}
+
+/// Everything `init.lua` declares. Evaluating `init.lua` only fills the
+/// manifest, the commands then decide what to do with it.
+#[derive(Debug, Clone, Default)]
+pub struct Manifest {
+    pub files: Vec<FileEntry>,
+    pub directories: Vec<DirectoryEntry>,
+    pub links: Vec<LinkEntry>,
+    /// The files copied outside of the worktree, declared with the `copy`
+    /// function.
+    pub copies: Vec<FileEntry>,
+    /// The groups of the host, if the `groups` function was called.
+    pub groups: Option<Vec<String>>,
+    /// The variables of the templates, declared with the `variables`
+    /// function.
+    pub variables: BTreeMap<String, minijinja::Value>,
+}
+
+/// Gives access to the manifest being filled, fails outside of `evaluate`.
+pub(crate) fn with_manifest<R>(
+    lua: &Lua,
+    f: impl FnOnce(&mut Manifest) -> R,
+) -> Result<R, Error> {
+    match lua.app_data_mut::<Manifest>() {
+        Some(mut manifest) => Ok(f(&mut manifest)),
+        None => Err(Error::external(
+            "Files can only be declared while evaluating `init.lua'.",
+        )),
+    }
+}
+
+/// Parses a mode given as an octal string, such as `"0600"`.
+pub(crate) fn parse_mode(mode: &str) -> Result<u32, Error> {
+    match u32::from_str_radix(mode, 8) {
+        Ok(mode) if mode <= 0o7777 => Ok(mode),
+        _ => Err(Error::external(format!(
+            "Invalid mode `{}', it must be an octal string such as \"0644\".",
+            mode
+        ))),
+    }
+}
+
+/// Fails if `table`, the options given to the Lua function `function`, has
+/// other keys than `options` and the `positional` first integer keys.
+pub(crate) fn check_options(
+    table: &Table,
+    function: &str,
+    options: &[&str],
+    positional: i64,
+) -> Result<(), Error> {
+    for pair in table.clone().pairs::<Value, Value>() {
+        match pair? {
+            (Value::Integer(i), _) if 1 <= i && i <= positional => (),
+            (Value::String(key), _)
+                if options.contains(&key.to_str().unwrap_or_default()) => {}
+            (key, _) => {
+                return Err(Error::external(format!(
+                    "Unknown option `{}', the options of `{}' are: {}.",
+                    match key {
+                        Value::String(key) => key.to_string_lossy().to_string(),
+                        key => key.type_name().to_string(),
+                    },
+                    function,
+                    options.join(", ")
+                )))
+            }
+        }
+    }
+
+    Ok(())
+}
+
+/// Expands the leading `~` of `path` to the home directory.
+pub(crate) fn expand_home(path: &str) -> PathBuf {
+    match (path.strip_prefix('~'), std::env::var("HOME")) {
+        (Some(rest), Ok(home)) => PathBuf::from(format!("{}{}", home, rest)),
+        _ => PathBuf::from(path),
+    }
+}
+
+/// Returns `path`, as given to a Lua function, relative to the worktree. A
+/// leading `~` stands for the home directory and relative paths are relative
+/// to the worktree.
+pub(crate) fn resolve(
+    path: &str,
+    worktree_path: &Path,
+) -> Result<PathBuf, Error> {
+    let expanded = expand_home(path);
+
+    if expanded.is_relative() {
+        return Ok(expanded);
+    }
+
+    let abs_wt = fs::canonicalize(worktree_path).map_err(Error::external)?;
+    let abs_path = match expanded.parent() {
+        Some(parent) if parent.exists() => fs::canonicalize(parent)
+            .map_err(Error::external)?
+            .join(expanded.file_name().unwrap_or_default()),
+        _ => expanded.clone(),
+    };
+
+    match abs_path.strip_prefix(&abs_wt) {
+        Ok(rel_path) => Ok(rel_path.to_path_buf()),
+        Err(_) => Err(Error::external(format!(
+            "The path {} is not in the worktree {}.",
+            path,
+            worktree_path.display()
+        ))),
+    }
+}
+
+/// Runs `source`, the content of `init.lua`, and returns what it declared.
+pub fn evaluate(
+    lua: &Lua,
+    source: &str,
+    name: &str,
+) -> Result<Manifest, Error> {
+    lua.set_app_data(Manifest::default());
+
+    let res = lua
+        .load(source)
+        .set_name(name)
+        .and_then(|chunk| chunk.exec());
+    let manifest = lua.remove_app_data::<Manifest>().unwrap_or_default();
+
+    res.map(|_| manifest)
+}
@@ -0,0 +1,23 @@
+use minijinja::{Environment, UndefinedBehavior, Value};
+
+use crate::{Facts, Manifest};
+
+pub use minijinja::Error as TemplateError;
+
+/// Renders `source`, the template `name`, with the variables of `manifest` and
+/// the facts as `facts`. Using a variable that was not declared is an error.
+pub fn render(
+    source: &str,
+    name: &str,
+    manifest: &Manifest,
+    facts: &Facts,
+) -> Result<String, TemplateError> {
+    let mut env = Environment::new();
+    env.set_undefined_behavior(UndefinedBehavior::Strict);
+    env.set_keep_trailing_newline(true);
+
+    let mut context = manifest.variables.clone();
+    context.insert("facts".to_string(), Value::from_serialize(facts));
+
+    env.render_named_str(name, source, context)
+}
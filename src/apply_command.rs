@@ -0,0 +1,32 @@
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{
+    config::{self, Config},
+    copies, deploy, run_command,
+};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "Error while running the apply command:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum ApplyError {
+    ConfigError(config::ConfigError),
+    RunError(run_command::RunCommandError),
+    DeployError(deploy::DeployError),
+    #[doc_to_string]
+    IoError(std::io::Error),
+}
+
+/// Deploys the configuration, then writes the saved version of the copies to
+/// their path outside of the worktree.
+pub(crate) fn run(config: Config) -> Result<(), ApplyError> {
+    let _lock = config.lock()?;
+    let manifest = run_command::load(&config)?;
+
+    run_command::apply_groups(&config, &manifest)?;
+    run_command::deploy(&config, &manifest)?;
+    for entry in copies::entries(&config, &manifest)? {
+        deploy::deploy(&config, &manifest, &entry)?;
+    }
+
+    Ok(())
+}
@@ -5,9 +5,19 @@ use clap::{Parser, Subcommand};
 #[derive(Debug, Parser)]
 #[clap(author, version, about, long_about = None)]
 pub(crate) struct Args {
-    /// Path to the configuration to work with, default is `$HOME/.config/rdm`
-    #[clap(short, long, value_parser)]
+    /// Path to the configuration to work with, default is
+    /// `$XDG_CONFIG_HOME/rdm`, or `~/.config/rdm` if `XDG_CONFIG_HOME` is not
+    /// set.
+    #[clap(short, long, value_parser, env = "RDM_CONFIG_DIR")]
     pub config_path: Option<String>,
+    /// Report what the command would change without changing the index, the
+    /// lockfile, the references, the remotes or the worktree.
+    #[clap(long, global = true, value_parser)]
+    pub dry_run: bool,
+    /// Fail instead of downloading the files missing from the download
+    /// cache.
+    #[clap(long, global = true, value_parser)]
+    pub offline: bool,
     #[clap(subcommand)]
     pub command: Commands,
 }
@@ -16,13 +26,13 @@ pub(crate) struct Args {
 pub(crate) enum Commands {
     /// Initialize the rdm folders and local repository.
     Init {
-        /// Path to the local bare repository to create, if none is provided then then default is
-        /// `~/config/rdm/repo`.
+        /// Path to the local bare repository to create, if none is provided then the default is
+        /// `$XDG_DATA_HOME/rdm/repo`, or `~/.local/share/rdm/repo` if `XDG_DATA_HOME` is not set.
         #[clap(long, short, value_parser)]
         repo: Option<String>,
         /// If given, then a default configuration file will be created at this location, if not
-        /// then if there are no files at `~/.config/rdm/init.lua` the default file will be created
-        /// at this location.
+        /// then if there are no files at `init.lua` in the configuration directory the default
+        /// file will be created at this location.
         #[clap(long, short, value_parser)]
         config: Option<String>,
         /// Path of the git worktree, if none is given then it defaults to your
@@ -30,11 +40,46 @@ pub(crate) enum Commands {
         #[clap(long, short, value_parser)]
         worktree: Option<String>,
     },
+    /// Clone an existing configuration and set it up for the current host.
+    Clone {
+        /// The url of the configuration repository to clone.
+        #[clap(value_parser)]
+        url: String,
+        /// Path to the local bare repository to create, if none is provided
+        /// then the default is `$XDG_DATA_HOME/rdm/repo`.
+        #[clap(long, short, value_parser)]
+        repo: Option<String>,
+        /// Path of the git worktree, if none is given then it defaults to your
+        /// current directory.
+        #[clap(long, short, value_parser)]
+        worktree: Option<String>,
+        /// The host whose configuration is forked when the current host has no
+        /// branch yet, if none is given you will be asked to choose one.
+        #[clap(long, short, value_parser)]
+        from: Option<String>,
+    },
     /// Manage your configuration git repository.
     #[clap(subcommand)]
     Config(ConfigSubCommand),
     /// Run the `bootstrap.lua` file.
     Bootstrap,
+    /// Manage the hosts of your configuration.
+    #[clap(subcommand)]
+    Host(HostSubCommand),
+    /// Manage the backups of the files overwritten by rdm.
+    #[clap(subcommand)]
+    Backup(BackupSubCommand),
+    /// Deploy the configuration, including the copies outside of the worktree
+    /// which are only written by this command.
+    Apply,
+    /// Manage the encrypted secret files.
+    #[clap(subcommand)]
+    Secret(SecretSubCommand),
+    /// Manage the cache of the files downloaded by `curl`.
+    #[clap(subcommand)]
+    Cache(CacheSubCommand),
+    /// Check that your configuration is set up correctly.
+    Doctor,
 }
 
 #[derive(Debug, Subcommand)]
@@ -44,12 +89,26 @@ pub(crate) enum ConfigSubCommand {
         /// Path for files or folder ot be added to the configuration.
         #[clap(required = true, value_parser)]
         path: Vec<PathBuf>,
+        /// Add the files to the shared layer, common to every host.
+        #[clap(short, long, value_parser, conflicts_with = "group")]
+        shared: bool,
+        /// Add the files to the layer of a group of hosts.
+        #[clap(short, long, value_parser)]
+        group: Option<String>,
+        /// Add files from outside of the worktree, such as `/etc`, as copies
+        /// written back by `rdm apply`.
+        #[clap(long, value_parser, conflicts_with_all = &["shared", "group"])]
+        copy: bool,
     },
     /// Stage the changes
     Update {
         /// If given only stage the paths given, otherwise stage all the changes.
         #[clap(value_parser)]
         path: Vec<PathBuf>,
+        /// Apply the edits made to rendered templates to the templates instead
+        /// of refusing them.
+        #[clap(long, value_parser)]
+        reverse_apply: bool,
     },
     /// Show the status of your current config, the `(unsaved)' flag note the
     /// files that have not been updated yet in your config, run `config update`
@@ -69,6 +128,29 @@ pub(crate) enum ConfigSubCommand {
     Push,
     /// Pull your configuration from the default remote.
     Pull,
+    /// Commit the saved version of files of the current host onto other hosts,
+    /// without checking them out.
+    Propagate {
+        /// Path of the files to propagate.
+        #[clap(required = true, value_parser)]
+        path: Vec<PathBuf>,
+        /// The hosts to propagate the files to.
+        #[clap(
+            short,
+            long,
+            value_parser,
+            multiple_values = true,
+            required_unless_present = "all",
+            conflicts_with = "all"
+        )]
+        to: Vec<String>,
+        /// Propagate the files to every other host.
+        #[clap(short, long, value_parser)]
+        all: bool,
+        /// Overwrite the files that the hosts changed on their own.
+        #[clap(short, long, value_parser)]
+        force: bool,
+    },
     /// Only run the `init.lua` file
     Run,
 }
@@ -92,3 +174,83 @@ pub(crate) enum RemoteSubCommand {
     /// List all remotes
     List,
 }
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum BackupSubCommand {
+    /// List the backups and the files they contain.
+    List,
+    /// Restore the files of a backup into the worktree.
+    Restore {
+        /// The id of the backup, as given by `backup list`.
+        id: String,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum SecretSubCommand {
+    /// Re-encrypt the secret files of the current host with a new passphrase,
+    /// or with a key file.
+    Rekey {
+        /// Encrypt with the content of this file instead of a passphrase, a
+        /// random key is written to it if it does not exist.
+        #[clap(long, value_parser)]
+        key_file: Option<String>,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum CacheSubCommand {
+    /// List the cached downloads, from the least recently used.
+    List,
+    /// Remove the cached downloads that were not used recently.
+    Prune {
+        /// Remove the downloads not used for this many days, 0 removes all of
+        /// them.
+        #[clap(long, value_parser, default_value_t = 30)]
+        days: u64,
+    },
+}
+
+#[derive(Debug, Subcommand)]
+pub(crate) enum HostSubCommand {
+    /// List the hosts with their last revision.
+    List,
+    /// Create a new host from the configuration of another one.
+    Create {
+        /// The name of the new host.
+        name: String,
+        /// The host to fork, default is the current host.
+        #[clap(short, long, value_parser)]
+        from: Option<String>,
+    },
+    /// Switch the worktree to the configuration of another host.
+    Switch {
+        /// The host to switch to.
+        name: String,
+        /// Discard the unsaved changes of the current configuration.
+        #[clap(short, long, value_parser)]
+        force: bool,
+    },
+    /// Rename a host.
+    Rename {
+        /// The current name of the host.
+        old: String,
+        /// The new name of the host.
+        new: String,
+    },
+    /// Delete a host.
+    Delete { name: String },
+    /// Compare the configuration of two hosts.
+    Diff {
+        /// The first host.
+        host_a: String,
+        /// The second host.
+        host_b: String,
+        /// Only compare these paths.
+        #[clap(value_parser)]
+        paths: Vec<PathBuf>,
+        /// Only show a summary of the changes.
+        #[clap(long, value_parser)]
+        stat: bool,
+    },
+}
@@ -0,0 +1,254 @@
+use std::{
+    ffi::OsStr,
+    os::unix::ffi::OsStrExt,
+    path::{Path, PathBuf},
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+use git2::{build::CheckoutBuilder, ObjectType, Oid, Repository, Tree};
+use rdm_macros::{FromError, ToDoc};
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "Error while backing up your files:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum BackupError {
+    #[doc_to_string]
+    IoError(std::io::Error),
+    #[doc_to_string]
+    GitError(git2::Error),
+    #[doc_to_string]
+    TomlDeError(toml::de::Error),
+    #[doc_to_string]
+    TomlSerError(toml::ser::Error),
+    #[doc_to_string]
+    TimeError(std::time::SystemTimeError),
+    #[doc_text = "The repository does not have a worktree."]
+    NoWorktree,
+    #[doc_format(format_str = "The backup `{}' does not exist.", _1)]
+    #[no_from]
+    NotFound(String),
+}
+
+/// Content of the `manifest.toml` file stored in each backup directory.
+#[derive(Serialize, Deserialize, Debug)]
+pub(crate) struct BackupManifest {
+    pub(crate) id: String,
+    /// Seconds since the unix epoch at which the backup was made.
+    pub(crate) created: u64,
+    /// What triggered the backup.
+    pub(crate) reason: String,
+    /// Paths of the saved files, relative to the worktree.
+    pub(crate) files: Vec<String>,
+}
+
+const MANIFEST: &str = "manifest.toml";
+const FILES_DIR: &str = "files";
+
+fn backups_dir(config_path: &Path) -> PathBuf {
+    let mut path = config_path.to_path_buf();
+    path.push("backups");
+    path
+}
+
+fn backup_dir(config_path: &Path, id: &str) -> PathBuf {
+    let mut path = backups_dir(config_path);
+    path.push(id);
+    path
+}
+
+/// Returns `true` if the file at `path` exists and its content matches none
+/// of the blobs in `oids`, meaning it cannot be recovered from the repository.
+fn differs(path: &Path, oids: &[Oid]) -> Result<bool, BackupError> {
+    if !path.is_file() {
+        return Ok(false);
+    }
+
+    let oid = Oid::hash_file(ObjectType::Blob, path)?;
+    Ok(!oids.contains(&oid))
+}
+
+/// Lists the worktree files a forced checkout of `target` would overwrite, or
+/// remove because they are in the index but not in `target`, relative to the
+/// worktree. Files whose content is already stored in the index are skipped.
+pub(crate) fn files_to_overwrite(
+    repo: &Repository,
+    target: &Tree,
+) -> Result<Vec<PathBuf>, BackupError> {
+    let worktree = repo.workdir().ok_or(BackupError::NoWorktree)?;
+    let index = repo.index()?;
+    let mut files = Vec::new();
+    let mut walk_err = None;
+
+    target.walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+        if entry.kind() != Some(ObjectType::Blob) {
+            return git2::TreeWalkResult::Ok;
+        }
+        // The names are not always UTF-8, they are kept as they are.
+        let rel = Path::new(root).join(OsStr::from_bytes(entry.name_bytes()));
+        let mut known = vec![entry.id()];
+        if let Some(indexed) = index.get_path(&rel, 0) {
+            known.push(indexed.id);
+        }
+        match differs(&worktree.join(&rel), &known) {
+            Ok(true) => files.push(rel),
+            Ok(false) => (),
+            Err(err) => {
+                walk_err = Some(err);
+                return git2::TreeWalkResult::Abort;
+            }
+        }
+        git2::TreeWalkResult::Ok
+    })?;
+
+    if let Some(err) = walk_err {
+        return Err(err);
+    }
+
+    for entry in index.iter() {
+        let rel = PathBuf::from(String::from_utf8_lossy(&entry.path).as_ref());
+        if target.get_path(&rel).is_err()
+            && differs(&worktree.join(&rel), &[entry.id])?
+        {
+            files.push(rel);
+        }
+    }
+
+    Ok(files)
+}
+
+/// Copies `files`, relative to the worktree or absolute, into a new
+/// timestamped backup directory. Returns the id of the backup, or `None` if
+/// there was nothing to save.
+pub(crate) fn create(
+    repo: &Repository,
+    config_path: &Path,
+    files: &[PathBuf],
+    reason: &str,
+) -> Result<Option<String>, BackupError> {
+    if files.is_empty() {
+        return Ok(None);
+    }
+
+    let worktree = repo.workdir().ok_or(BackupError::NoWorktree)?;
+    let created = SystemTime::now().duration_since(UNIX_EPOCH)?.as_secs();
+
+    let mut id = created.to_string();
+    let mut n = 1;
+    while backup_dir(config_path, &id).exists() {
+        id = format!("{}-{}", created, n);
+        n += 1;
+    }
+
+    let dir = backup_dir(config_path, &id);
+    for rel in files {
+        // The absolute paths are saved under their path from the root.
+        let mut dest = dir.join(FILES_DIR);
+        dest.push(rel.strip_prefix("/").unwrap_or(rel));
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(worktree.join(rel), &dest)?;
+    }
+
+    let manifest = BackupManifest {
+        id: id.clone(),
+        created,
+        reason: reason.to_string(),
+        files: files
+            .iter()
+            .map(|f| f.to_string_lossy().to_string())
+            .collect(),
+    };
+    std::fs::write(dir.join(MANIFEST), toml::to_string(&manifest)?)?;
+
+    log::warn!(
+        "{} file(s) that would be overwritten were saved in the backup `{}'.",
+        files.len(),
+        id
+    );
+
+    Ok(Some(id))
+}
+
+/// Backs up the files that would be changed by checking out HEAD, then
+/// force-checks it out.
+pub(crate) fn safe_checkout_head(
+    repo: &Repository,
+    config_path: &Path,
+    reason: &str,
+    builder: &mut CheckoutBuilder,
+) -> Result<Option<String>, BackupError> {
+    let tree = repo.head()?.peel_to_tree()?;
+    safe_checkout_tree(repo, config_path, &tree, reason, builder)
+}
+
+/// Backs up the files that would be changed by checking out `tree`, then
+/// force-checks it out.
+pub(crate) fn safe_checkout_tree(
+    repo: &Repository,
+    config_path: &Path,
+    tree: &Tree,
+    reason: &str,
+    builder: &mut CheckoutBuilder,
+) -> Result<Option<String>, BackupError> {
+    let files = files_to_overwrite(repo, tree)?;
+    let id = create(repo, config_path, &files, reason)?;
+
+    repo.checkout_tree(tree.as_object(), Some(builder.force()))?;
+
+    // The checkout leaves the index as is for the files that already had the
+    // content of `tree` in the worktree.
+    let mut index = repo.index()?;
+    index.read_tree(tree)?;
+    index.write()?;
+
+    Ok(id)
+}
+
+pub(crate) fn list(
+    config_path: &Path,
+) -> Result<Vec<BackupManifest>, BackupError> {
+    let dir = backups_dir(config_path);
+    let mut manifests = Vec::new();
+
+    if !dir.exists() {
+        return Ok(manifests);
+    }
+
+    for entry in std::fs::read_dir(dir)? {
+        let manifest_path = entry?.path().join(MANIFEST);
+        if manifest_path.is_file() {
+            let str = std::fs::read_to_string(manifest_path)?;
+            manifests.push(toml::from_str(&str)?);
+        }
+    }
+
+    manifests.sort_by(|a: &BackupManifest, b| {
+        a.created.cmp(&b.created).then_with(|| a.id.cmp(&b.id))
+    });
+
+    Ok(manifests)
+}
+
+pub(crate) fn load(
+    config_path: &Path,
+    id: &str,
+) -> Result<BackupManifest, BackupError> {
+    let manifest_path = backup_dir(config_path, id).join(MANIFEST);
+
+    if !manifest_path.is_file() {
+        return Err(BackupError::NotFound(id.to_string()));
+    }
+
+    let str = std::fs::read_to_string(manifest_path)?;
+    Ok(toml::from_str(&str)?)
+}
+
+/// Path of a file saved in the backup `id`.
+pub(crate) fn saved_file(config_path: &Path, id: &str, rel: &str) -> PathBuf {
+    let mut path = backup_dir(config_path, id);
+    path.push(FILES_DIR);
+    path.push(rel.strip_prefix('/').unwrap_or(rel));
+    path
+}
@@ -0,0 +1,55 @@
+use pretty::RcDoc;
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{backup, config::Config};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "list error:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum ListError {
+    BackupError(backup::BackupError),
+    #[doc_to_string]
+    IoError(std::io::Error),
+    #[doc_to_string]
+    ToString(std::string::FromUtf8Error),
+}
+
+pub(super) fn run(config: Config) -> Result<(), ListError> {
+    let backups = backup::list(&config.config_path)?;
+
+    if backups.is_empty() {
+        println!("No backups were made.");
+        return Ok(());
+    }
+
+    let doc = RcDoc::<()>::text("Available backups:")
+        .append(RcDoc::line())
+        .append(
+            RcDoc::intersperse(
+                backups.iter().map(|manifest| {
+                    RcDoc::text(format!(
+                        "{}: {} file(s) saved before {}",
+                        manifest.id,
+                        manifest.files.len(),
+                        manifest.reason
+                    ))
+                    .append(
+                        RcDoc::line()
+                            .append(RcDoc::intersperse(
+                                manifest.files.iter().map(RcDoc::text),
+                                RcDoc::line(),
+                            ))
+                            .nest(2),
+                    )
+                }),
+                RcDoc::line(),
+            )
+            .nest(1),
+        );
+    let mut buf = Vec::new();
+    let (_, cols) = console::Term::stdout().size();
+    doc.render(cols.into(), &mut buf)?;
+    let str = String::from_utf8(buf)?;
+    println!("{}", str);
+    Ok(())
+}
@@ -0,0 +1,32 @@
+mod list_subcommand;
+mod restore_subcommand;
+
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{
+    args::BackupSubCommand,
+    config::{self, Config},
+};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "Backup Command Error:"]
+pub(crate) enum BackupCommandError {
+    ListError(list_subcommand::ListError),
+    RestoreError(restore_subcommand::RestoreError),
+    ConfigError(config::ConfigError),
+}
+
+pub(crate) fn run(
+    sub_command: BackupSubCommand,
+    config: Config,
+) -> Result<(), BackupCommandError> {
+    match sub_command {
+        BackupSubCommand::List => list_subcommand::run(config)?,
+        BackupSubCommand::Restore { id } => {
+            let _lock = config.lock()?;
+            restore_subcommand::run(config, id)?
+        }
+    };
+
+    Ok(())
+}
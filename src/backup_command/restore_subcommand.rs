@@ -0,0 +1,55 @@
+use std::path::PathBuf;
+
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{backup, config::Config};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "restore error:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum RestoreError {
+    BackupError(backup::BackupError),
+    #[doc_to_string]
+    IoError(std::io::Error),
+}
+
+pub(super) fn run(config: Config, id: String) -> Result<(), RestoreError> {
+    let manifest = backup::load(&config.config_path, &id)?;
+
+    // The files about to be overwritten are saved first, in case the wrong
+    // backup is restored.
+    let mut overwritten = Vec::new();
+    for file in manifest.files.iter() {
+        let current = config.worktree_path.join(file);
+        let saved = backup::saved_file(&config.config_path, &id, file);
+        if current.is_file()
+            && std::fs::read(&current)? != std::fs::read(&saved)?
+        {
+            overwritten.push(PathBuf::from(file));
+        }
+    }
+    if let Some(saved) = backup::create(
+        &config.repo,
+        &config.config_path,
+        &overwritten,
+        "restoring",
+    )? {
+        log::info!("The replaced files were saved in the backup `{}'.", saved);
+    }
+
+    for file in manifest.files.iter() {
+        let saved = backup::saved_file(&config.config_path, &id, file);
+        let mut dest = config.worktree_path.clone();
+        dest.push(file);
+
+        if let Some(parent) = dest.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(saved, &dest)?;
+        log::info!("Restored {}", file);
+    }
+
+    log::info!("The backup `{}' was restored.", id);
+
+    Ok(())
+}
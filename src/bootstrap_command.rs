@@ -9,6 +9,7 @@ pub(crate) enum BootstrapError {
     NoBootstrapFile,
     #[doc_to_string]
     LuaError(mlua::Error),
+    RuntimeError(rdm_lua::RuntimeError),
     #[doc_to_string]
     IoError(std::io::Error),
 }
@@ -27,6 +28,7 @@ pub(crate) fn run(config: config::Config) -> Result<(), BootstrapError> {
 
     lua.load(&str)
         .set_name(lua_path.to_str().unwrap())?
-        .exec()?;
+        .exec()
+        .map_err(rdm_lua::RuntimeError::from_lua)?;
     Ok(())
 }
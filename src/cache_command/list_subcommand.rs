@@ -0,0 +1,53 @@
+use chrono::{DateTime, Local};
+use indicatif::HumanBytes;
+use pretty::RcDoc;
+use rdm_lua::DownloadCache;
+use rdm_macros::{FromError, ToDoc};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "list error:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum ListError {
+    #[doc_to_string]
+    IoError(std::io::Error),
+    #[doc_to_string]
+    ToString(std::string::FromUtf8Error),
+}
+
+pub(super) fn run(cache: &DownloadCache) -> Result<(), ListError> {
+    let entries = cache.entries()?;
+
+    if entries.is_empty() {
+        println!("The download cache is empty.");
+        return Ok(());
+    }
+
+    let total: u64 = entries.iter().map(|entry| entry.size).sum();
+    let lines = entries.iter().map(|entry| {
+        let date = DateTime::<Local>::from(entry.last_used)
+            .format("%Y-%m-%d %H:%M")
+            .to_string();
+
+        RcDoc::text(format!(
+            "{} {} ({}, used {})",
+            &entry.sha256[..12],
+            entry.url,
+            HumanBytes(entry.size),
+            date
+        ))
+    });
+
+    let doc =
+        RcDoc::<()>::text(format!("Cached downloads ({}):", HumanBytes(total)))
+            .append(
+                RcDoc::line()
+                    .append(RcDoc::intersperse(lines, RcDoc::line()))
+                    .nest(1),
+            );
+    let mut buf = Vec::new();
+    let (_, cols) = console::Term::stdout().size();
+    doc.render(cols.into(), &mut buf)?;
+    let str = String::from_utf8(buf)?;
+    println!("{}", str);
+    Ok(())
+}
@@ -0,0 +1,33 @@
+mod list_subcommand;
+mod prune_subcommand;
+
+use rdm_lua::DownloadCache;
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{args::CacheSubCommand, config, utils};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "Cache Command Error:"]
+pub(crate) enum CacheCommandError {
+    ListError(list_subcommand::ListError),
+    PruneError(prune_subcommand::PruneError),
+    #[doc_to_string]
+    ExpandError(utils::ExpandError),
+}
+
+/// Runs a cache command, which does not need a configuration.
+pub(crate) fn run(
+    sub_command: CacheSubCommand,
+    dry_run: bool,
+) -> Result<(), CacheCommandError> {
+    let cache = DownloadCache::new(config::cache_path()?, false);
+
+    match sub_command {
+        CacheSubCommand::List => list_subcommand::run(&cache)?,
+        CacheSubCommand::Prune { days } => {
+            prune_subcommand::run(&cache, days, dry_run)?
+        }
+    };
+
+    Ok(())
+}
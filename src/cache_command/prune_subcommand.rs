@@ -0,0 +1,85 @@
+use std::time::{Duration, SystemTime};
+
+use indicatif::HumanBytes;
+use rdm_lua::{CacheEntry, DownloadCache};
+use rdm_macros::{FromError, ToDoc};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "prune error:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum PruneError {
+    #[doc_to_string]
+    IoError(std::io::Error),
+}
+
+/// Whether `entry` was not used for `days` days before `now`, every entry is
+/// stale for 0 days.
+fn is_stale(entry: &CacheEntry, days: u64, now: SystemTime) -> bool {
+    let limit = now
+        .checked_sub(Duration::from_secs(days * 24 * 60 * 60))
+        .unwrap_or(SystemTime::UNIX_EPOCH);
+
+    days == 0 || entry.last_used < limit
+}
+
+/// Removes the downloads of `cache` that were not used for `days` days.
+pub(super) fn run(
+    cache: &DownloadCache,
+    days: u64,
+    dry_run: bool,
+) -> Result<(), PruneError> {
+    let now = SystemTime::now();
+
+    let mut freed = 0;
+    for entry in cache.entries()? {
+        if !is_stale(&entry, days, now) {
+            continue;
+        }
+
+        if dry_run {
+            log::info!("Would remove {} from the cache.", entry.url);
+        } else {
+            cache.remove(&entry)?;
+            log::info!("Removed {} from the cache.", entry.url);
+        }
+        freed += entry.size;
+    }
+
+    if !dry_run {
+        log::info!("Freed {}.", HumanBytes(freed));
+    }
+
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn used(last_used: SystemTime) -> CacheEntry {
+        CacheEntry {
+            url: "file:///a".to_string(),
+            sha256: "0".repeat(64),
+            size: 1,
+            last_used,
+        }
+    }
+
+    #[test]
+    fn stale_after_days() {
+        let now = SystemTime::now();
+
+        assert!(!is_stale(&used(now - DAY), 30, now));
+        assert!(!is_stale(&used(now - 29 * DAY), 30, now));
+        assert!(is_stale(&used(now - 31 * DAY), 30, now));
+    }
+
+    #[test]
+    fn everything_stale_for_zero_days() {
+        let now = SystemTime::now();
+
+        assert!(is_stale(&used(now), 0, now));
+    }
+}
@@ -0,0 +1,250 @@
+use std::path::PathBuf;
+
+use gethostname::gethostname;
+use git2::build::{CheckoutBuilder, RepoBuilder};
+use git2::{BranchType, Cred, FetchOptions, RemoteCallbacks, Repository};
+use rdm_macros::{FromError, ToDoc};
+
+use crate::backup;
+use crate::config::{self, Config};
+use crate::config_command::remote_commands::default_subcommand;
+use crate::layers;
+use crate::lockfile::{self, TomlConfig};
+use crate::run_command;
+use crate::utils;
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "Error while running the `clone' command:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum CloneError {
+    #[doc_to_string]
+    IoError(std::io::Error),
+    #[doc_to_string]
+    GitError(git2::Error),
+    #[doc_to_string]
+    EnvError(std::env::VarError),
+    #[doc_to_string]
+    ExpandError(utils::ExpandError),
+    LockFileError(lockfile::LockFileError),
+    BackupError(backup::BackupError),
+    ConfigError(config::ConfigError),
+    RunError(run_command::RunCommandError),
+    #[doc_format(
+        format_str = "The path `{}' already exists and is not empty.",
+        _1
+    )]
+    #[no_from]
+    RepoNotEmpty(String),
+    #[doc_text = "The cloned repository does not contain any host branch."]
+    NoHostBranch,
+    #[doc_format(
+        format_str = "The host `{}' was not found, available hosts are: {}.",
+        _1,
+        _2
+    )]
+    #[no_from]
+    HostNotFound(String, String),
+}
+
+/// Remote the configuration is cloned from.
+const ORIGIN: &str = "origin";
+
+fn remote_hosts(repo: &Repository) -> Result<Vec<String>, CloneError> {
+    let prefix = format!("{}/", ORIGIN);
+    let shared = layers::shared_branch(repo)?;
+    let mut hosts = Vec::new();
+
+    for branch in repo.branches(Some(BranchType::Remote))? {
+        let (branch, _) = branch?;
+        if let Some(name) = branch.name()? {
+            match name.strip_prefix(&prefix) {
+                Some("HEAD") | None => (),
+                Some(host)
+                    if host == shared
+                        || host.starts_with(layers::GROUP_PREFIX) => {}
+                Some(host) => hosts.push(host.to_string()),
+            }
+        }
+    }
+
+    Ok(hosts)
+}
+
+/// Creates the local branches of the shared layer and of the groups from the
+/// cloned ones.
+fn create_layer_branches(repo: &Repository) -> Result<(), CloneError> {
+    let prefix = format!("{}/", ORIGIN);
+    let shared = layers::shared_branch(repo)?;
+
+    for branch in repo.branches(Some(BranchType::Remote))? {
+        let (branch, _) = branch?;
+        let name = match branch.name()?.and_then(|n| n.strip_prefix(&prefix)) {
+            Some(name) => name.to_string(),
+            None => continue,
+        };
+
+        if (name == shared || name.starts_with(layers::GROUP_PREFIX))
+            && repo.find_branch(&name, BranchType::Local).is_err()
+        {
+            repo.branch(&name, &branch.get().peel_to_commit()?, false)?;
+        }
+    }
+
+    Ok(())
+}
+
+/// Asks the user which host branch should be forked for the current host.
+fn ask_fork_source(host: &str, hosts: &[String]) -> Result<String, CloneError> {
+    let term = console::Term::stdout();
+
+    if !term.is_term() {
+        return Err(CloneError::HostNotFound(
+            host.to_string(),
+            hosts.join(", "),
+        ));
+    }
+
+    println!(
+        "There is no configuration for the host `{}', choose one to fork:",
+        host
+    );
+    for (i, name) in hosts.iter().enumerate() {
+        println!("{:2}{}) {}", "", i + 1, name);
+    }
+
+    let answer = term.read_line()?;
+    let answer = answer.trim();
+
+    let choice = match answer.parse::<usize>() {
+        Ok(i) if i >= 1 && i <= hosts.len() => Some(hosts[i - 1].clone()),
+        _ => hosts.iter().find(|name| name.as_str() == answer).cloned(),
+    };
+
+    choice.ok_or_else(|| {
+        CloneError::HostNotFound(answer.to_string(), hosts.join(", "))
+    })
+}
+
+pub(crate) fn run(
+    url: String,
+    repo_path: Option<String>,
+    worktree: Option<String>,
+    from: Option<String>,
+    config_path: Option<String>,
+    offline: bool,
+) -> Result<(), CloneError> {
+    let repo_path_buf = match &repo_path {
+        None => config::default_repo_path()?,
+        Some(path) => PathBuf::from(utils::full_expand(path)?),
+    };
+
+    if repo_path_buf.exists() && repo_path_buf.read_dir()?.next().is_some() {
+        return Err(CloneError::RepoNotEmpty(
+            repo_path_buf.to_str().unwrap().to_string(),
+        ));
+    }
+
+    let worktree_path = match &worktree {
+        Some(str) => {
+            let path = PathBuf::from(utils::full_expand(str)?);
+            if !path.exists() {
+                log::info!("Creating worktree path {}", path.display());
+                std::fs::create_dir_all(&path)?;
+            }
+            std::fs::canonicalize(path)?
+        }
+        None => std::env::current_dir()?,
+    };
+
+    let mut cbs = RemoteCallbacks::new();
+    cbs.credentials(|_, username, _| {
+        Cred::ssh_key_from_agent(username.unwrap_or("git"))
+    });
+
+    let mut fetch_opts = FetchOptions::default();
+    fetch_opts.remote_callbacks(cbs);
+
+    log::info!("Cloning {} into {}", url, repo_path_buf.display());
+    let repo = RepoBuilder::new()
+        .bare(true)
+        .fetch_options(fetch_opts)
+        .clone(&url, &repo_path_buf)?;
+    let repo_path_buf = std::fs::canonicalize(repo_path_buf)?;
+
+    let hosts = remote_hosts(&repo)?;
+    if hosts.is_empty() {
+        return Err(CloneError::NoHostBranch);
+    }
+
+    let host = gethostname().into_string().unwrap();
+
+    let source = if hosts.contains(&host) {
+        host.clone()
+    } else {
+        let source = match from {
+            Some(from) => from,
+            None => ask_fork_source(&host, &hosts)?,
+        };
+        if !hosts.contains(&source) {
+            return Err(CloneError::HostNotFound(source, hosts.join(", ")));
+        }
+        log::info!("Forking the configuration of `{}' for `{}'", source, host);
+        source
+    };
+
+    let source_ref = format!("refs/remotes/{}/{}", ORIGIN, source);
+    let commit = repo.find_reference(&source_ref)?.peel_to_commit()?;
+
+    let host_branch = match repo.find_branch(&host, BranchType::Local) {
+        Ok(branch) => branch,
+        Err(_) => repo.branch(&host, &commit, false)?,
+    };
+
+    create_layer_branches(&repo)?;
+
+    repo.config()?
+        .open_level(git2::ConfigLevel::Local)?
+        .set_str("status.showUntrackedFiles", "no")?;
+    repo.set_workdir(worktree_path.as_path(), false)?;
+
+    let config_dir = config::config_dir(&config_path)?;
+
+    repo.set_head(host_branch.get().name().unwrap())?;
+    layers::checkout_head(
+        &repo,
+        &config_dir,
+        "clone",
+        &mut CheckoutBuilder::default(),
+    )?;
+    log::info!("Checked out the configuration of `{}'", host);
+
+    default_subcommand::set_branch_remote(&repo, &host, ORIGIN)?;
+
+    std::fs::create_dir_all(&config_dir)?;
+    let lock_path = config_dir.join("rdm.lock");
+
+    let conf = TomlConfig::new(
+        &repo_path_buf.to_str().map(String::from),
+        &worktree_path.to_str(),
+    )?;
+    conf.save(&lock_path)?;
+
+    // The checkout only wrote the saved files, the targets, modes and links
+    // declared by `init.lua` are deployed like on a host switch.
+    if config_dir.join("init.lua").exists() {
+        let config = Config::open(&config_path, false, offline)?;
+        let _lock = config.lock()?;
+        let manifest = run_command::load(&config)?;
+        run_command::apply_groups(&config, &manifest)?;
+        run_command::deploy(&config, &manifest)?;
+    } else {
+        log::warn!(
+            "There is no `init.lua' in {}, nothing was deployed.",
+            config_dir.display()
+        );
+    }
+
+    log::info!("Your configuration is ready to be used.");
+
+    Ok(())
+}
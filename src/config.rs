@@ -1,9 +1,16 @@
 use rdm_macros::{FromError, ToDoc};
 
 use crate::args::Args;
+use crate::hosts;
 use crate::lockfile::{self, TomlConfig};
 use crate::utils;
-use std::path::PathBuf;
+use std::{
+    cell::OnceCell,
+    fs::File,
+    io::{Read, Write},
+    os::unix::{fs::MetadataExt, io::AsRawFd},
+    path::{Path, PathBuf},
+};
 
 /// Internal rdm configuration
 pub(crate) struct Config {
@@ -12,7 +19,13 @@ pub(crate) struct Config {
     pub(crate) worktree_path: PathBuf,
     pub(crate) repo: git2::Repository,
     pub(crate) lua: mlua::Lua,
-    pub(crate) revision: u32,
+    /// What the Lua files know about this machine.
+    pub(crate) facts: rdm_lua::Facts,
+    /// The key material of the secret files, read the first time it is
+    /// needed.
+    pub(crate) secret_key: OnceCell<Vec<u8>>,
+    /// Only report what would change, see `--dry-run`.
+    pub(crate) dry_run: bool,
 }
 
 #[derive(Debug, FromError, ToDoc)]
@@ -27,15 +40,133 @@ pub(crate) enum ConfigError {
     #[doc_to_string]
     IOError(std::io::Error),
     LuaRuntimeError(rdm_lua::RuntimeError),
+    #[doc_to_string]
+    ExpandError(utils::ExpandError),
+    #[doc_format(
+        format_str = "Another rdm process ({}) is using this configuration.",
+        _1
+    )]
+    #[no_from]
+    LockedError(String),
+}
+
+/// Name of the file locking the configuration directory.
+const PROCESS_LOCK: &str = "rdm.pid";
+
+/// Advisory lock preventing several rdm processes from using the same
+/// configuration at once, it is released when dropped, or by the system if
+/// rdm is killed.
+pub(crate) struct ProcessLock {
+    path: PathBuf,
+    /// The locked file, the lock lasts as long as it is open.
+    _file: File,
+}
+
+impl Drop for ProcessLock {
+    fn drop(&mut self) {
+        // The file is removed while it is still locked, a process waiting for
+        // it then sees that it was replaced.
+        if let Err(err) = std::fs::remove_file(&self.path) {
+            log::warn!(
+                "Could not remove the lock {}: {}",
+                self.path.to_string_lossy(),
+                err
+            );
+        }
+    }
+}
+
+/// Whether `file` is still the one at `path`, and not a lock file removed by
+/// the process that held it.
+fn is_lock_file(file: &File, path: &Path) -> Result<bool, std::io::Error> {
+    let opened = file.metadata()?;
+
+    match std::fs::metadata(path) {
+        Ok(current) => {
+            Ok(current.dev() == opened.dev() && current.ino() == opened.ino())
+        }
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(false),
+        Err(err) => Err(err),
+    }
+}
+
+/// The configuration directory, `config_path` if given, which can also be set
+/// with `RDM_CONFIG_DIR`, and `$XDG_CONFIG_HOME/rdm` otherwise.
+pub(crate) fn config_dir(
+    config_path: &Option<String>,
+) -> Result<PathBuf, utils::ExpandError> {
+    Ok(match config_path {
+        Some(config_path) => utils::full_expand(config_path.as_str())?,
+        None => utils::full_expand("$XDG_CONFIG_HOME/rdm")?,
+    }
+    .into())
+}
+
+/// The default path of the bare repository, `$XDG_DATA_HOME/rdm/repo`.
+pub(crate) fn default_repo_path() -> Result<PathBuf, utils::ExpandError> {
+    Ok(utils::full_expand("$XDG_DATA_HOME/rdm/repo")?.into())
+}
+
+/// The directory of the download cache, `$XDG_DATA_HOME/rdm/cache`.
+pub(crate) fn cache_path() -> Result<PathBuf, utils::ExpandError> {
+    Ok(utils::full_expand("$XDG_DATA_HOME/rdm/cache")?.into())
 }
 
 impl Config {
-    pub(crate) fn new(args: &Args) -> Result<Config, ConfigError> {
-        let mut expanded: PathBuf = match &args.config_path {
-            Some(config_path) => utils::full_expand(config_path.as_str()),
-            None => utils::full_expand("~/.config/rdm/"),
+    /// Takes the lock of the configuration directory, it fails right away if
+    /// another process holds it.
+    pub(crate) fn lock(&self) -> Result<ProcessLock, ConfigError> {
+        let path = self.config_path.join(PROCESS_LOCK);
+
+        loop {
+            let mut file = std::fs::OpenOptions::new()
+                .read(true)
+                .write(true)
+                .create(true)
+                .truncate(false)
+                .open(&path)?;
+
+            let res = unsafe {
+                libc::flock(file.as_raw_fd(), libc::LOCK_EX | libc::LOCK_NB)
+            };
+            if res != 0 {
+                let err = std::io::Error::last_os_error();
+                if err.raw_os_error() != Some(libc::EWOULDBLOCK) {
+                    return Err(err.into());
+                }
+
+                // The holder may not have written its PID yet.
+                let mut holder = String::new();
+                file.read_to_string(&mut holder)?;
+                return Err(ConfigError::LockedError(
+                    match holder.trim().parse::<u32>() {
+                        Ok(pid) => format!("PID {}", pid),
+                        Err(_) => "unknown PID".to_string(),
+                    },
+                ));
+            }
+
+            if !is_lock_file(&file, &path)? {
+                continue;
+            }
+
+            file.set_len(0)?;
+            write!(file, "{}", std::process::id())?;
+            return Ok(ProcessLock { path, _file: file });
         }
-        .into();
+    }
+
+    pub(crate) fn new(args: &Args) -> Result<Config, ConfigError> {
+        Config::open(&args.config_path, args.dry_run, args.offline)
+    }
+
+    /// Opens the configuration at `config_path`, see [`config_dir`].
+    pub(crate) fn open(
+        config_path: &Option<String>,
+        dry_run: bool,
+        offline: bool,
+    ) -> Result<Config, ConfigError> {
+        let mut expanded = config_dir(config_path)?;
 
         let config_path = expanded.clone();
 
@@ -46,25 +177,28 @@ impl Config {
             return Err(ConfigError::NoLockFile);
         }
 
-        let TomlConfig {
-            revision,
-            repo_path,
-            worktree_path,
-        } = TomlConfig::load(&expanded)?;
+        let lock = TomlConfig::load(&expanded, dry_run)?;
 
-        let repo_path: PathBuf = repo_path.into();
-        let worktree_path: PathBuf = worktree_path.into();
+        let repo_path = lock.repo_path()?;
+        let worktree_path = lock.worktree_path()?;
         let repo = git2::Repository::open_bare(&repo_path)?;
         repo.set_workdir(worktree_path.as_path(), false)?;
 
-        let lua = rdm_lua::init(repo_path, worktree_path.clone())?;
+        let facts = rdm_lua::Facts::gather(
+            hosts::current_host(&repo).ok().flatten(),
+            env!("CARGO_PKG_VERSION"),
+        );
+        let cache = rdm_lua::DownloadCache::new(cache_path()?, offline);
+        let lua = rdm_lua::init(worktree_path.clone(), facts.clone(), cache)?;
 
         Ok(Config {
             config_path,
             repo,
-            revision,
             worktree_path,
             lua,
+            facts,
+            secret_key: OnceCell::new(),
+            dry_run,
         })
     }
 }
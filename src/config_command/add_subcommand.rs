@@ -1,8 +1,11 @@
-use std::path::{Path, PathBuf};
+use std::{
+    os::unix::fs::PermissionsExt,
+    path::{Path, PathBuf},
+};
 
 use rdm_macros::{FromError, ToDoc};
 
-use crate::config;
+use crate::{config, copies, deploy, layers};
 
 #[derive(Debug, FromError, ToDoc)]
 #[doc_prefix = "Error while runnit the `add' subcommand:"]
@@ -12,21 +15,40 @@ pub(crate) enum AddSubcommandError {
     GitError(git2::Error),
     #[doc_to_string]
     IoError(std::io::Error),
+    DeployError(deploy::DeployError),
+    #[doc_format(
+        format_str = "The path {} is not in the worktree, add it with `--copy'.",
+        _1
+    )]
+    #[no_from]
+    OutsideWorktree(String),
+    #[doc_format(
+        format_str = "Only files can be added as copies, not {}.",
+        _1
+    )]
+    #[no_from]
+    NotAFile(String),
+    #[doc_text = "The copies are saved in the configuration directory, which \
+                  is not in the worktree."]
+    NoCopiesDir,
 }
 
 fn add_path(
     repo: &git2::Repository,
     index: &mut git2::Index,
     path: &Path,
+    dry_run: bool,
 ) -> Result<(), AddSubcommandError> {
     let status = repo.status_file(path)?;
 
-    if status.contains(git2::Status::WT_NEW)
-        || status.contains(git2::Status::WT_MODIFIED)
-    {
-        index.add_path(path.as_ref())?;
-        index.write()?;
-        log::info!("Added file: {}", path.to_str().unwrap());
+    if status.intersects(git2::Status::WT_NEW | git2::Status::WT_MODIFIED) {
+        if dry_run {
+            log::info!("Would add file: {}", path.to_str().unwrap());
+        } else {
+            index.add_path(path.as_ref())?;
+            index.write()?;
+            log::info!("Added file: {}", path.to_str().unwrap());
+        }
     } else {
         log::info!("File already added: {}", path.to_str().unwrap());
     }
@@ -34,21 +56,198 @@ fn add_path(
     Ok(())
 }
 
+/// Commits the files at `paths` to the layer `refname`, named `layer`, and
+/// stages the ones the current host gets from this layer.
+fn add_to_layer(
+    repo: &git2::Repository,
+    index: &mut git2::Index,
+    worktree_path: &Path,
+    paths: &[PathBuf],
+    refname: &str,
+    layer: &str,
+    dry_run: bool,
+) -> Result<(), AddSubcommandError> {
+    let mut layer_index = git2::Index::new()?;
+    if let Some(layer_tree) = layers::layer_tree(repo, refname)? {
+        layer_index.read_tree(&layer_tree)?;
+    }
+
+    for path in paths {
+        let abs_path = worktree_path.join(path);
+        let oid = repo.blob_path(&abs_path)?;
+        let mode = if abs_path.metadata()?.permissions().mode() & 0o111 != 0 {
+            0o100755
+        } else {
+            0o100644
+        };
+        layer_index.add(&layers::index_entry(
+            path.to_str().unwrap(),
+            oid,
+            mode,
+        ))?;
+        if !dry_run {
+            log::info!("Added file to {}: {}", layer, path.to_str().unwrap());
+        }
+    }
+
+    let names: Vec<&str> = paths.iter().map(|p| p.to_str().unwrap()).collect();
+    let msg = format!("Add {} to {}", names.join(", "), layer);
+
+    if dry_run {
+        log::info!("Would commit `{}' on {}.", msg, refname);
+        return Ok(());
+    }
+
+    let tree = repo.find_tree(layer_index.write_tree_to(repo)?)?;
+    layers::commit_layer(repo, refname, &tree, &msg)?;
+
+    for path in paths {
+        if layers::layer_of(repo, path)?.as_deref() == Some(refname) {
+            index.add_path(path)?;
+        } else {
+            log::warn!(
+                "The file {} of {} is not used by the current host.",
+                path.to_str().unwrap(),
+                layer
+            );
+        }
+    }
+    index.write()?;
+
+    Ok(())
+}
+
+/// Saves a copy of the files at `paths`, which may be outside of the
+/// worktree, under their absolute path in the copies directory and stages it.
+fn add_copies(
+    config: &config::Config,
+    index: &mut git2::Index,
+    paths: &[PathBuf],
+) -> Result<(), AddSubcommandError> {
+    let copies_dir =
+        copies::copies_dir(config).ok_or(AddSubcommandError::NoCopiesDir)?;
+
+    // Every path is checked before the first copy is made.
+    let mut copies = Vec::new();
+    for path in paths {
+        let abs_path = std::fs::canonicalize(path)?;
+        if !abs_path.is_file() {
+            return Err(AddSubcommandError::NotAFile(
+                path.to_string_lossy().to_string(),
+            ));
+        }
+
+        let mapped = copies::mapped_path(&copies_dir, &abs_path);
+        copies.push((abs_path, mapped));
+    }
+
+    if config.dry_run {
+        for (abs_path, mapped) in copies {
+            log::info!(
+                "Would add a copy of {} at {}.",
+                abs_path.to_str().unwrap(),
+                mapped.to_str().unwrap()
+            );
+        }
+        return Ok(());
+    }
+
+    for (abs_path, mapped) in copies {
+        let abs_mapped = config.worktree_path.join(&mapped);
+        if let Some(parent) = abs_mapped.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+        std::fs::copy(&abs_path, &abs_mapped)?;
+        deploy::mark_deployed(config, &abs_path)?;
+        index.add_path(&mapped)?;
+
+        log::info!(
+            "Added a copy of {} at {}.",
+            abs_path.to_str().unwrap(),
+            mapped.to_str().unwrap()
+        );
+    }
+    index.write()?;
+
+    Ok(())
+}
+
 pub(super) fn run(
     config: config::Config,
     path: Vec<PathBuf>,
+    shared: bool,
+    group: Option<String>,
+    copy: bool,
 ) -> Result<(), AddSubcommandError> {
+    let mut index = config.repo.index()?;
+
+    if copy {
+        return add_copies(&config, &mut index, &path);
+    }
+
     let repo = config.repo;
 
-    let mut index = repo.index()?;
+    let rel_paths = path
+        .iter()
+        .map(|path| {
+            let abs_path = std::fs::canonicalize(path)?;
+            match abs_path.strip_prefix(&config.worktree_path) {
+                Ok(rel_path) => Ok(rel_path.to_path_buf()),
+                Err(_) => Err(AddSubcommandError::OutsideWorktree(
+                    path.to_string_lossy().to_string(),
+                )),
+            }
+        })
+        .collect::<Result<Vec<PathBuf>, AddSubcommandError>>()?;
 
-    println!("{:#?}", path);
+    if shared {
+        let refname = layers::shared_ref(&repo)?;
+        return add_to_layer(
+            &repo,
+            &mut index,
+            &config.worktree_path,
+            &rel_paths,
+            &refname,
+            "the shared layer",
+            config.dry_run,
+        );
+    }
 
-    path.iter().try_for_each(|path| {
-        let abs_path = std::fs::canonicalize(path)?;
-        let rel_path =
-            pathdiff::diff_paths(abs_path, &config.worktree_path).unwrap();
-        add_path(&repo, &mut index, rel_path.as_path())
+    if let Some(group) = group {
+        if !layers::groups(&repo)?.contains(&group) {
+            log::warn!("The current host is not in the group {}.", group);
+        }
+        return add_to_layer(
+            &repo,
+            &mut index,
+            &config.worktree_path,
+            &rel_paths,
+            &layers::group_ref(&group),
+            &format!("the group {}", group),
+            config.dry_run,
+        );
+    }
+
+    rel_paths.iter().try_for_each(|path| {
+        if layers::layer_of(&repo, path)?.is_none() {
+            return add_path(&repo, &mut index, path.as_path(), config.dry_run);
+        }
+
+        if config.dry_run {
+            log::info!(
+                "Would make the file {} override its layer for this host.",
+                path.to_str().unwrap()
+            );
+        } else {
+            layers::add_override(&repo, path.to_str().unwrap())?;
+            index.add_path(path)?;
+            index.write()?;
+            log::info!(
+                "The file {} will override its layer for this host.",
+                path.to_str().unwrap()
+            );
+        }
+        Ok(())
     })?;
 
     Ok(())
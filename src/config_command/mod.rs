@@ -1,14 +1,19 @@
 mod add_subcommand;
+mod propagate_subcommand;
 mod pull_subcommand;
 mod push_subcommand;
-mod remote_commands;
+pub(crate) mod remote_commands;
 mod save_subcommand;
 mod status_subcommand;
 mod update_subcommand;
 
 use rdm_macros::{FromError, ToDoc};
 
-use crate::{args::ConfigSubCommand, config::Config, run_command};
+use crate::{
+    args::ConfigSubCommand,
+    config::{self, Config},
+    run_command,
+};
 
 #[derive(Debug, FromError, ToDoc)]
 #[doc_prefix = "Config Command Error:"]
@@ -20,29 +25,99 @@ pub(crate) enum ConfigCommandError {
     RemoteSubCommandError(remote_commands::RemoteError),
     PushSubcommandError(push_subcommand::PushError),
     PullSubcommandError(pull_subcommand::PullError),
+    PropagateSubcommandError(propagate_subcommand::PropagateError),
     RunError(run_command::RunCommandError),
+    ConfigError(config::ConfigError),
+    #[doc_format(
+        format_str = "The file {} is a secret, it is saved encrypted by `rdm \
+                      config update'.",
+        _1
+    )]
+    #[no_from]
+    SecretTarget(String),
 }
 
 pub(crate) fn run(
     sub_command: ConfigSubCommand,
     config: Config,
 ) -> Result<(), ConfigCommandError> {
-    run_command::run(&config)?;
+    let _lock = config.lock()?;
+
+    // Only the commands working on the files of the configuration need what
+    // `init.lua` declares, `pull` loads it once the new version is checked
+    // out.
+    let manifest = match sub_command {
+        ConfigSubCommand::Remote(_)
+        | ConfigSubCommand::Push
+        | ConfigSubCommand::Pull => None,
+        _ => Some(run_command::load(&config)?),
+    };
+
     match sub_command {
-        ConfigSubCommand::Add { path } => add_subcommand::run(config, path)?,
-        ConfigSubCommand::Update { path } => {
+        ConfigSubCommand::Add {
+            path,
+            shared,
+            group,
+            copy,
+        } => {
+            for path in path.iter() {
+                let manifest = manifest.as_ref().unwrap();
+                if run_command::secret_entry(&config, manifest, path)?.is_some()
+                {
+                    return Err(ConfigCommandError::SecretTarget(
+                        path.to_string_lossy().to_string(),
+                    ));
+                }
+            }
+            add_subcommand::run(config, path, shared, group, copy)?
+        }
+        ConfigSubCommand::Update {
+            path,
+            reverse_apply,
+        } => {
+            if path.is_empty() {
+                run_command::stage(
+                    &config,
+                    manifest.as_ref().unwrap(),
+                    reverse_apply,
+                )?;
+            }
+            let path = run_command::target_sources(
+                &config,
+                manifest.as_ref().unwrap(),
+                path,
+            )?;
             update_subcommand::run(config, path)?
         }
         ConfigSubCommand::Status { untracked } => {
-            status_subcommand::run(config, untracked)?
+            status_subcommand::run(config, manifest.unwrap(), untracked)?
         }
         ConfigSubCommand::Remote(sub_command) => {
             remote_commands::run(config, sub_command)?
         }
-        ConfigSubCommand::Save => save_subcommand::run(config)?,
+        ConfigSubCommand::Save => {
+            run_command::apply_groups(&config, manifest.as_ref().unwrap())?;
+            run_command::stage(&config, manifest.as_ref().unwrap(), false)?;
+            save_subcommand::run(config)?
+        }
         ConfigSubCommand::Push => push_subcommand::run(config)?,
-        ConfigSubCommand::Pull => pull_subcommand::run(config)?,
-        ConfigSubCommand::Run => (),
+        ConfigSubCommand::Pull => {
+            pull_subcommand::run(&config)?;
+            let manifest = run_command::load(&config)?;
+            run_command::apply_groups(&config, &manifest)?;
+            run_command::deploy(&config, &manifest)?
+        }
+        ConfigSubCommand::Propagate {
+            path,
+            to,
+            all,
+            force,
+        } => propagate_subcommand::run(config, path, to, all, force)?,
+        ConfigSubCommand::Run => {
+            run_command::apply_groups(&config, manifest.as_ref().unwrap())?;
+            run_command::stage(&config, manifest.as_ref().unwrap(), false)?;
+            run_command::deploy(&config, manifest.as_ref().unwrap())?
+        }
     };
 
     Ok(())
@@ -0,0 +1,190 @@
+use std::{
+    collections::HashSet,
+    path::{Path, PathBuf},
+};
+
+use git2::{Index, Oid, Repository, Tree};
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{config::Config, hosts, layers, revision, utils};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "Error while running the `propagate' subcommand:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum PropagateError {
+    #[doc_to_string]
+    GitError(git2::Error),
+    #[doc_to_string]
+    IoError(std::io::Error),
+    #[doc_text = "HEAD is not on a host branch."]
+    HeadNotBranch,
+    #[doc_format(format_str = "The host {} does not exist.", _1)]
+    #[no_from]
+    HostNotFound(String),
+    #[doc_format(
+        format_str = "The file {} is not saved in the current host.",
+        _1
+    )]
+    #[no_from]
+    NotInHost(String),
+    #[doc_text = "No host to propagate to, use `--to' or `--all'."]
+    NoTarget,
+}
+
+/// Every version `path` had in the history of the current host.
+fn saved_blobs(
+    repo: &Repository,
+    path: &Path,
+) -> Result<HashSet<Oid>, git2::Error> {
+    let mut revwalk = repo.revwalk()?;
+    revwalk.push_head()?;
+
+    let mut blobs = HashSet::new();
+    for oid in revwalk {
+        if let Ok(entry) = repo.find_commit(oid?)?.tree()?.get_path(path) {
+            blobs.insert(entry.id());
+        }
+    }
+
+    Ok(blobs)
+}
+
+/// Builds the new tree of a host from `tree` with the `files` of the current
+/// host.
+fn propagated_tree<'r>(
+    repo: &'r Repository,
+    tree: &Tree,
+    files: &[(PathBuf, Oid, u32)],
+) -> Result<Tree<'r>, git2::Error> {
+    let mut index = Index::new()?;
+    index.read_tree(tree)?;
+
+    for (path, oid, mode) in files {
+        index.add(&layers::index_entry(path.to_str().unwrap(), *oid, *mode))?;
+    }
+
+    repo.find_tree(index.write_tree_to(repo)?)
+}
+
+pub(super) fn run(
+    config: Config,
+    paths: Vec<PathBuf>,
+    to: Vec<String>,
+    all: bool,
+    force: bool,
+) -> Result<(), PropagateError> {
+    let repo = &config.repo;
+    let current =
+        hosts::current_host(repo)?.ok_or(PropagateError::HeadNotBranch)?;
+    let head_tree = repo.head()?.peel_to_tree()?;
+
+    let mut files = Vec::new();
+    for path in paths {
+        let path = utils::worktree_relative(&path, &config.worktree_path)?;
+        let entry = head_tree.get_path(&path).map_err(|_| {
+            PropagateError::NotInHost(path.to_string_lossy().to_string())
+        })?;
+        let saved = saved_blobs(repo, &path)?;
+        files.push((path, entry.id(), entry.filemode() as u32, saved));
+    }
+
+    let targets: Vec<String> = if all {
+        hosts::host_branches(repo)?
+            .into_iter()
+            .map(|(name, _)| name)
+            .filter(|name| *name != current)
+            .collect()
+    } else {
+        to.into_iter()
+            .filter(|name| {
+                if *name == current {
+                    log::warn!("Skipping {}, it is the current host.", name);
+                }
+                *name != current
+            })
+            .collect()
+    };
+
+    if targets.is_empty() {
+        return Err(PropagateError::NoTarget);
+    }
+
+    // Every host is checked before the first one is changed.
+    let mut branches = Vec::new();
+    for target in targets {
+        match hosts::find_host(repo, &target)? {
+            Some(branch) => branches.push((target, branch)),
+            None => return Err(PropagateError::HostNotFound(target)),
+        }
+    }
+
+    let names: Vec<String> = files
+        .iter()
+        .map(|(path, _, _, _)| path.to_string_lossy().to_string())
+        .collect();
+
+    for (target, branch) in branches {
+        let parent = branch.get().peel_to_commit()?;
+        let tree = parent.tree()?;
+
+        // The target changed a file on its own if its version was never
+        // saved by the current host.
+        let diverged: Vec<&str> = files
+            .iter()
+            .zip(names.iter())
+            .filter(|((path, _, _, saved), _)| match tree.get_path(path) {
+                Ok(entry) => !saved.contains(&entry.id()),
+                Err(_) => false,
+            })
+            .map(|(_, name)| name.as_str())
+            .collect();
+
+        if !diverged.is_empty() && !force {
+            log::warn!(
+                "Skipping {}, it has its own changes to {}, use `--force' to \
+                 overwrite them.",
+                target,
+                diverged.join(", ")
+            );
+            continue;
+        }
+
+        let changed: Vec<(PathBuf, Oid, u32)> = files
+            .iter()
+            .filter(|(path, oid, _, _)| match tree.get_path(path) {
+                Ok(entry) => entry.id() != *oid,
+                Err(_) => true,
+            })
+            .map(|(path, oid, mode, _)| (path.clone(), *oid, *mode))
+            .collect();
+
+        if changed.is_empty() {
+            log::info!("{} is already up to date.", target);
+            continue;
+        }
+
+        let changed_names = changed
+            .iter()
+            .map(|(path, _, _)| path.to_string_lossy())
+            .collect::<Vec<_>>()
+            .join(", ");
+        let new_tree = propagated_tree(repo, &tree, &changed)?;
+        let msg = revision::message(
+            revision::of_commit(&parent)? + 1,
+            Some(&format!("propagate {} from {}", changed_names, current)),
+        );
+        let sig = repo.signature()?;
+        repo.commit(
+            Some(branch.get().name().unwrap()),
+            &sig,
+            &sig,
+            &msg,
+            &new_tree,
+            &[&parent],
+        )?;
+
+        log::info!("Propagated {} to {}.", changed_names, target);
+    }
+
+    Ok(())
+}
@@ -1,10 +1,18 @@
 use git2::{
-    build::CheckoutBuilder, Cred, FetchOptions, MergeAnalysis, RemoteCallbacks,
+    build::CheckoutBuilder, Cred, Direction, FetchOptions, MergeAnalysis,
+    RemoteCallbacks,
 };
 use indicatif::{ProgressBar, ProgressStyle};
 use rdm_macros::{FromError, ToDoc};
 
-use crate::config::Config;
+use std::path::Path;
+
+use crate::{
+    backup,
+    config::Config,
+    layers,
+    lockfile::{self, TomlConfig},
+};
 
 #[derive(Debug, FromError, ToDoc)]
 #[doc_prefix = "Push error:"]
@@ -16,10 +24,204 @@ pub(crate) enum PullError {
     HeadNotBranch,
     #[doc_to_string]
     FromUtf8Error(std::string::FromUtf8Error),
+    BackupError(backup::BackupError),
+    LockFileError(lockfile::LockFileError),
+    #[doc_to_string]
+    IoError(std::io::Error),
+}
+
+/// Resolves the conflict on the lockfile at `lock_path`, caused by the versions
+/// of rdm that saved the revision in it. The local lockfile is kept, upgraded
+/// to the current format which no longer holds the revision.
+fn resolve_lockfile_conflict(
+    repo: &git2::Repository,
+    idx: &mut git2::Index,
+    lock_path: &Path,
+) -> Result<(), PullError> {
+    let conflict = idx.conflicts()?.find(|conflict| match conflict {
+        Ok(conflict) => conflict.our.as_ref().is_some_and(|our| {
+            our.path == lock_path.to_str().unwrap().as_bytes()
+        }),
+        Err(_) => false,
+    });
+
+    if let Some(conflict) = conflict {
+        let our = conflict?.our.unwrap();
+        let blob = repo.find_blob(our.id)?;
+        let (lock, _) =
+            TomlConfig::parse(&String::from_utf8(blob.content().to_vec())?)?;
+        let oid = repo.blob(lock.contents()?.as_bytes())?;
+
+        idx.remove_path(lock_path)?;
+        idx.add(&layers::index_entry(
+            lock_path.to_str().unwrap(),
+            oid,
+            our.mode,
+        ))?;
+        log::info!("Resolved the revision conflict on the lockfile.");
+    }
+
+    Ok(())
+}
+
+/// Remote-tracking reference of the layer `layer_ref` for `remote_name`.
+fn remote_layer_ref(layer_ref: &str, remote_name: &str) -> String {
+    format!(
+        "refs/remotes/{}/{}",
+        remote_name,
+        layer_ref.trim_start_matches("refs/heads/")
+    )
 }
 
-pub(super) fn run(config: Config) -> Result<(), PullError> {
-    let repo = config.repo;
+/// Updates the layer `layer_ref` from the one fetched from `remote_name`.
+/// Returns `true` if the layer changed.
+fn pull_layer(
+    repo: &git2::Repository,
+    remote_name: &str,
+    layer_ref: &str,
+) -> Result<bool, PullError> {
+    let remote_ref = remote_layer_ref(layer_ref, remote_name);
+
+    let remote_commit = match repo.find_reference(&remote_ref) {
+        Ok(reference) => reference.peel_to_commit()?,
+        Err(_) => return Ok(false),
+    };
+
+    let local_commit = match layers::layer_commit(repo, layer_ref)? {
+        Some(commit) => commit,
+        None => {
+            repo.reference(
+                layer_ref,
+                remote_commit.id(),
+                false,
+                &format!("Setting {} to {}", layer_ref, remote_commit.id()),
+            )?;
+            log::info!("Fetched {} from {}.", layer_ref, remote_name);
+            return Ok(true);
+        }
+    };
+
+    let base = repo.merge_base(local_commit.id(), remote_commit.id())?;
+
+    if base == remote_commit.id() {
+        Ok(false)
+    } else if base == local_commit.id() {
+        repo.reference(
+            layer_ref,
+            remote_commit.id(),
+            true,
+            &format!("Setting {} to {}", layer_ref, remote_commit.id()),
+        )?;
+        log::info!("Fast forwarded {}.", layer_ref);
+        Ok(true)
+    } else {
+        let mut idx =
+            repo.merge_commits(&local_commit, &remote_commit, None)?;
+
+        if idx.has_conflicts() {
+            log::warn!(
+                "{} conflicts with {}, it was not updated.",
+                layer_ref,
+                remote_ref
+            );
+            return Ok(false);
+        }
+
+        let result_tree = repo.find_tree(idx.write_tree_to(repo)?)?;
+        let msg =
+            format!("Merge: {} into {}", remote_commit.id(), local_commit.id());
+        let sig = repo.signature()?;
+
+        repo.commit(
+            Some(layer_ref),
+            &sig,
+            &sig,
+            &msg,
+            &result_tree,
+            &[&local_commit, &remote_commit],
+        )?;
+        log::info!("Successfully merged {}.", layer_ref);
+        Ok(true)
+    }
+}
+
+/// Reports how pulling would update the references `refnames` from
+/// `remote`, only listing its references without fetching them.
+fn preview(
+    repo: &git2::Repository,
+    remote: &mut git2::Remote,
+    refnames: &[String],
+) -> Result<(), PullError> {
+    let mut cbs = RemoteCallbacks::new();
+    cbs.credentials(|_, username, _| {
+        Cred::ssh_key_from_agent(username.unwrap())
+    });
+
+    let remote_name = remote.name().unwrap_or_default().to_string();
+    let connection = remote.connect_auth(Direction::Fetch, Some(cbs), None)?;
+    let heads = connection.list()?;
+
+    for refname in refnames {
+        let remote_id = match heads.iter().find(|head| head.name() == refname) {
+            Some(head) => head.oid(),
+            None => {
+                log::info!("{} does not exist on {}.", refname, remote_name);
+                continue;
+            }
+        };
+
+        let local_id = match repo.refname_to_id(refname) {
+            Ok(id) => id,
+            Err(_) => {
+                log::info!(
+                    "Would create {} from {}: {}",
+                    refname,
+                    remote_name,
+                    remote_id
+                );
+                continue;
+            }
+        };
+
+        if repo.find_commit(remote_id).is_err() {
+            log::info!(
+                "Would fetch {} from {} and update it: {}..{}",
+                refname,
+                remote_name,
+                local_id,
+                remote_id
+            );
+            continue;
+        }
+
+        let base = repo.merge_base(local_id, remote_id)?;
+        if base == remote_id {
+            log::info!("{} is already up to date.", refname);
+        } else if base == local_id {
+            log::info!(
+                "Would fast forward {}: {}..{}",
+                refname,
+                local_id,
+                remote_id
+            );
+        } else {
+            log::info!(
+                "Would merge {} of {} into {}: {}",
+                refname,
+                remote_name,
+                local_id,
+                remote_id
+            );
+        }
+    }
+
+    Ok(())
+}
+
+pub(super) fn run(config: &Config) -> Result<(), PullError> {
+    let dry_run = config.dry_run;
+    let repo = &config.repo;
+    let config_path = &config.config_path;
 
     let head = repo.head()?;
 
@@ -76,11 +278,36 @@ pub(super) fn run(config: Config) -> Result<(), PullError> {
 
         fetch_opts.remote_callbacks(cbs);
 
+        let remote_branch = format!("{}/{}", remote_name, branch_name);
+        let host_refspec =
+            format!("+{}:refs/remotes/{}", refspec, remote_branch);
+        let layer_refs = layers::layer_refs(repo)?;
+
+        if dry_run {
+            let mut refnames = vec![refspec.to_string()];
+            refnames.extend(layer_refs.iter().cloned());
+            return preview(repo, &mut remote, &refnames);
+        }
+
         log::info!("Fetching {}/{}", remote_name, branch_name);
+        let mut refspecs = vec![host_refspec];
+        refspecs.extend(layer_refs.iter().map(|layer_ref| {
+            format!(
+                "+{}:{}",
+                layer_ref,
+                remote_layer_ref(layer_ref, &remote_name)
+            )
+        }));
 
-        remote.fetch(&[refspec], Some(&mut fetch_opts), None)?;
+        remote.fetch(&refspecs, Some(&mut fetch_opts), None)?;
 
-        let fetch_head = repo.find_reference("FETCH_HEAD")?;
+        let mut layers_updated = false;
+        for layer_ref in layer_refs.iter() {
+            layers_updated |= pull_layer(repo, &remote_name, layer_ref)?;
+        }
+
+        let fetch_head =
+            repo.find_reference(&format!("refs/remotes/{}", remote_branch))?;
 
         let fetch_commit = repo.reference_to_annotated_commit(&fetch_head)?;
 
@@ -98,9 +325,12 @@ pub(super) fn run(config: Config) -> Result<(), PullError> {
                         );
                         ref_.set_target(fetch_commit.id(), &msg)?;
                         repo.set_head(&ref_name)?;
-                        repo.checkout_head(Some(
-                            CheckoutBuilder::default().force(),
-                        ))?;
+                        layers::checkout_head(
+                            repo,
+                            config_path,
+                            "pull",
+                            &mut CheckoutBuilder::default(),
+                        )?;
                     }
                     Err(_) => {
                         repo.reference(
@@ -115,16 +345,18 @@ pub(super) fn run(config: Config) -> Result<(), PullError> {
                             .as_str(),
                         )?;
                         repo.set_head(&ref_name)?;
-                        repo.checkout_head(Some(
+                        layers::checkout_head(
+                            repo,
+                            config_path,
+                            "pull",
                             CheckoutBuilder::default()
                                 .allow_conflicts(true)
-                                .conflict_style_merge(true)
-                                .force(),
-                        ))?;
+                                .conflict_style_merge(true),
+                        )?;
                     }
                 }
 
-                log::info!("Fast forwarded to FETCH_HEAD.")
+                log::info!("Fast forwarded to {}.", remote_branch)
             }
             MergeAnalysis::ANALYSIS_NORMAL => {
                 let head_commit =
@@ -146,6 +378,13 @@ pub(super) fn run(config: Config) -> Result<(), PullError> {
                     None,
                 )?;
 
+                let lock_path = pathdiff::diff_paths(
+                    std::fs::canonicalize(config_path.join("rdm.lock"))?,
+                    std::fs::canonicalize(repo.workdir().unwrap())?,
+                )
+                .unwrap();
+                resolve_lockfile_conflict(repo, &mut idx, &lock_path)?;
+
                 if idx.has_conflicts() {
                     log::warn!("Merge conflicts detected...");
                     for conflict in idx.conflicts()? {
@@ -161,7 +400,7 @@ pub(super) fn run(config: Config) -> Result<(), PullError> {
                     return Ok(());
                 }
 
-                let result_tree = repo.find_tree(idx.write_tree_to(&repo)?)?;
+                let result_tree = repo.find_tree(idx.write_tree_to(repo)?)?;
 
                 let msg = format!(
                     "Merge: {} into {}",
@@ -181,13 +420,27 @@ pub(super) fn run(config: Config) -> Result<(), PullError> {
                     &[&local_commit, &remote_commit],
                 )?;
 
-                repo.checkout_head(None)?;
+                layers::checkout_head(
+                    repo,
+                    config_path,
+                    "pull",
+                    &mut CheckoutBuilder::default(),
+                )?;
 
                 log::info!(
-                    "Successfully merged FETCH_HEAD into {}",
+                    "Successfully merged {} into {}",
+                    remote_branch,
                     branch_name
                 );
             }
+            _ if layers_updated => {
+                layers::checkout_head(
+                    repo,
+                    config_path,
+                    "pull",
+                    &mut CheckoutBuilder::default(),
+                )?;
+            }
             _ => log::info!("Your configuration is already up to date."),
         };
 
@@ -2,7 +2,7 @@ use git2::{Cred, PushOptions, RemoteCallbacks};
 use indicatif::{ProgressBar, ProgressStyle};
 use rdm_macros::{FromError, ToDoc};
 
-use crate::config::Config;
+use crate::{config::Config, layers};
 
 #[derive(Debug, FromError, ToDoc)]
 #[doc_prefix = "Push error:"]
@@ -14,7 +14,46 @@ pub(crate) enum PushError {
     HeadNotBranch,
 }
 
+/// Reports the references that pushing `refspecs` to `remote_name` would
+/// update, as far as the remote-tracking references know.
+fn preview(
+    repo: &git2::Repository,
+    remote_name: &str,
+    refspecs: &[String],
+) -> Result<(), PushError> {
+    for refname in refspecs {
+        let local = repo.refname_to_id(refname)?;
+        let tracking = format!(
+            "refs/remotes/{}/{}",
+            remote_name,
+            refname.trim_start_matches("refs/heads/")
+        );
+
+        match repo.refname_to_id(&tracking) {
+            Ok(remote) if remote == local => {
+                log::info!("{} is already up to date.", refname)
+            }
+            Ok(remote) => log::info!(
+                "Would push {} to {}: {}..{}",
+                refname,
+                remote_name,
+                remote,
+                local
+            ),
+            Err(_) => log::info!(
+                "Would push {} to {}: {}",
+                refname,
+                remote_name,
+                local
+            ),
+        }
+    }
+
+    Ok(())
+}
+
 pub(super) fn run(config: Config) -> Result<(), PushError> {
+    let dry_run = config.dry_run;
     let repo = config.repo;
 
     let head = repo.head()?;
@@ -79,6 +118,17 @@ pub(super) fn run(config: Config) -> Result<(), PushError> {
 
         push_opts.remote_callbacks(cbs);
 
+        let mut refspecs = vec![refspec.to_string()];
+        for layer_ref in layers::layer_refs(&repo)? {
+            if layers::layer_commit(&repo, &layer_ref)?.is_some() {
+                refspecs.push(layer_ref);
+            }
+        }
+
+        if dry_run {
+            return preview(&repo, &remote_name, &refspecs);
+        }
+
         log::info!(
             "Pushing {} to {}/{}",
             branch_name,
@@ -86,7 +136,7 @@ pub(super) fn run(config: Config) -> Result<(), PushError> {
             branch_name
         );
 
-        remote.push(&[refspec], Some(&mut push_opts))?;
+        remote.push(&refspecs, Some(&mut push_opts))?;
 
         log::info!("Successfully pushed your configuration to {}", remote_name);
 
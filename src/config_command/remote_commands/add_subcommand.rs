@@ -28,6 +28,14 @@ pub(super) fn run(
         Ok(_) => return Err(RemoteAddError::AlreadyExists(name)),
     };
 
+    if config.dry_run {
+        log::info!("Would add the remote `{}' with url: {}", name, url);
+        if default {
+            default_subcommand::preview(repo, &name)?;
+        }
+        return Ok(());
+    }
+
     repo.remote(&name, &url)?;
     log::info!("Remote `{}' was added with url: {}", name, url);
 
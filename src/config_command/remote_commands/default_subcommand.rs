@@ -15,6 +15,50 @@ pub(crate) enum DefaultError {
     RemoteNotFound(String),
 }
 
+/// Sets `remote` as the push/fetch remote of the local branch `branch_name`,
+/// tracking the branch of the same name on the remote.
+pub(crate) fn set_branch_remote(
+    repo: &git2::Repository,
+    branch_name: &str,
+    remote: &str,
+) -> Result<(), git2::Error> {
+    let mut config = repo.config()?;
+    let refs = format!("refs/heads/{}", branch_name);
+
+    let remote_key = format!("branch.{}.remote", branch_name);
+    let merge_key = format!("branch.{}.merge", branch_name);
+
+    config.set_str(&remote_key, remote)?;
+    log::info!("Set the remote for {} to {}", branch_name, remote);
+
+    config.set_str(&merge_key, &refs)?;
+    log::info!("Set the merge ref for {} to {}", branch_name, refs);
+
+    Ok(())
+}
+
+/// Reports the changes `set_branch_remote` would make to the current branch.
+pub(super) fn preview(
+    repo: &git2::Repository,
+    remote: &str,
+) -> Result<(), DefaultError> {
+    let head = repo.head()?;
+
+    if !head.is_branch() {
+        return Err(DefaultError::HeadNoBranch);
+    }
+
+    let branch_name = head.shorthand().unwrap();
+    log::info!("Would set the remote for {} to {}", branch_name, remote);
+    log::info!(
+        "Would set the merge ref for {} to {}",
+        branch_name,
+        head.name().unwrap()
+    );
+
+    Ok(())
+}
+
 pub(super) fn run(config: Config, name: String) -> Result<(), DefaultError> {
     let repo = config.repo;
 
@@ -23,19 +67,13 @@ pub(super) fn run(config: Config, name: String) -> Result<(), DefaultError> {
         return Err(DefaultError::RemoteNotFound(name));
     }
 
+    if config.dry_run {
+        return preview(&repo, &name);
+    }
+
     if head.is_branch() {
-        let mut config = repo.config()?;
-        let refs = head.name().unwrap().to_string();
         let head_name = head.shorthand().unwrap().to_string();
-
-        let remote_key = format!("branch.{}.remote", head_name);
-        let merge_key = format!("branch.{}.merge", head_name);
-
-        config.set_str(&remote_key, &name)?;
-        log::info!("Set the remote for {} to {}", head_name, name);
-
-        config.set_str(&merge_key, &refs)?;
-        log::info!("Set the merge ref for {} to {}", head_name, refs);
+        set_branch_remote(&repo, &head_name, &name)?;
     } else {
         return Err(DefaultError::HeadNoBranch);
     }
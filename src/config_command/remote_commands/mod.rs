@@ -3,7 +3,7 @@ use rdm_macros::{FromError, ToDoc};
 use crate::{args::RemoteSubCommand, config::Config};
 
 mod add_subcommand;
-mod default_subcommand;
+pub(crate) mod default_subcommand;
 mod list_subcommand;
 mod remove_subcommand;
 
@@ -19,6 +19,11 @@ pub(super) fn run(config: Config, name: String) -> Result<(), RemoveError> {
         return Err(RemoveError::DoesNotExists(name));
     }
 
+    if config.dry_run {
+        log::info!("Would delete the remote {}", name);
+        return Ok(());
+    }
+
     repo.remote_delete(name.as_str())?;
     log::info!("Remote {} was deleted", name);
 
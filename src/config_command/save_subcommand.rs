@@ -1,63 +1,75 @@
-use git2::{Status, StatusOptions};
+use git2::Status;
 use rdm_macros::{FromError, ToDoc};
 
-use crate::{
-    config::Config,
-    lockfile::{self, increment_revision},
-};
+use crate::{config::Config, layers, revision};
 
 #[derive(Debug, FromError, ToDoc)]
 #[doc_prefix = "Error while running the `save' subcommand:"]
 pub(crate) enum SaveSubcommandError {
     #[doc_text = "No changes were saved to the config, try to run `rdm config update'."]
     NoChangesError,
-    LockFileError(lockfile::LockFileError),
     #[doc_to_string]
     GitError(git2::Error),
+    #[doc_to_string]
+    IoError(std::io::Error),
 }
 
 pub(super) fn run(config: Config) -> Result<(), SaveSubcommandError> {
     let repo = &config.repo;
-    let revision = config.revision;
-
-    let mut status_opts = StatusOptions::new();
-    status_opts.include_ignored(false);
-    status_opts.include_unmodified(false);
-    status_opts.include_untracked(false);
 
-    let statuses_before = repo.statuses(Some(&mut status_opts))?;
+    let statuses_before = layers::statuses(repo, false)?;
+    let overrides = layers::pending_overrides(repo)?;
 
-    if statuses_before.is_empty() {
+    if statuses_before.is_empty() && overrides.is_empty() {
         return Err(SaveSubcommandError::NoChangesError);
     }
 
-    increment_revision(&config)?;
-
     let mut index = repo.index()?;
     let oid = index.write_tree()?;
     let sig = repo.signature()?;
     let parent = repo.head()?;
     let parent = parent.peel_to_commit()?;
     let tree = repo.find_tree(oid)?;
+    let (tree, layer_trees) = layers::split(repo, &tree, &overrides)?;
 
-    let msg = format!("Revision #{}", revision);
+    let revision = revision::current(repo)? + 1;
+    let msg = revision::message(revision, None);
 
-    repo.commit(Some("HEAD"), &sig, &sig, &msg, &tree, &[&parent])?;
+    if config.dry_run {
+        let head = repo.head()?;
+        let refnames: Vec<&str> = std::iter::once(head.name().unwrap())
+            .chain(layer_trees.iter().map(|(refname, _)| refname.as_str()))
+            .collect();
+        log::info!(
+            "Would save the revision #{} of your config on {} with the \
+             following changes:",
+            revision,
+            refnames.join(", ")
+        );
+    } else {
+        repo.commit(Some("HEAD"), &sig, &sig, &msg, &tree, &[&parent])?;
 
-    log::info!(
-        "The revision #{} of your config was saved with the following changes:",
-        revision
-    );
-    statuses_before.iter().for_each(|e| match e.status() {
-        Status::INDEX_NEW => rdm_log::StatusLogger::new_file(e.path().unwrap()),
-        Status::INDEX_MODIFIED => {
-            rdm_log::StatusLogger::modified_file(e.path().unwrap())
+        for (refname, layer_tree) in layer_trees {
+            layers::commit_layer(repo, &refname, &layer_tree, &msg)?;
         }
-        Status::INDEX_DELETED => {
-            rdm_log::StatusLogger::removed_file(e.path().unwrap())
-        }
-        _ => (),
-    });
+        layers::clear_overrides(repo)?;
+
+        log::info!(
+            "The revision #{} of your config was saved with the following \
+             changes:",
+            revision
+        );
+    }
+    statuses_before
+        .iter()
+        .for_each(|(path, status)| match *status {
+            Status::INDEX_NEW => rdm_log::StatusLogger::new_file(path),
+            Status::INDEX_MODIFIED => {
+                rdm_log::StatusLogger::modified_file(path)
+            }
+            Status::INDEX_DELETED => rdm_log::StatusLogger::removed_file(path),
+            _ => (),
+        });
 
     Ok(())
 }
@@ -1,64 +1,136 @@
-use git2::{Status, StatusOptions};
+use git2::Status;
 use rdm_macros::{FromError, ToDoc};
 
-use crate::config::Config;
+use rdm_lua::Manifest;
+
+use crate::{
+    config::Config,
+    copies,
+    deploy::{self, DeployState},
+    layers,
+    links::{self, LinkState},
+    run_command,
+};
 
 #[derive(Debug, FromError, ToDoc)]
 #[doc_prefix = "Error while running the `status' subcommand:"]
 pub(crate) enum StatusSubcommandError {
     #[doc_to_string]
     GitError(git2::Error),
+    #[doc_to_string]
+    IoError(std::io::Error),
+    RunError(run_command::RunCommandError),
+    DeployError(deploy::DeployError),
+    LinkError(links::LinkError),
 }
 
 pub(super) fn run(
     config: Config,
+    manifest: Manifest,
     show_untracked: bool,
 ) -> Result<(), StatusSubcommandError> {
-    let repo = config.repo;
-    let mut status_opts = StatusOptions::new();
-    status_opts.include_unmodified(false);
-    status_opts.include_untracked(show_untracked);
-    status_opts.include_ignored(false);
+    let repo = &config.repo;
+
+    // The files declared in `init.lua' that are not in the configuration yet.
+    let mut declared_new = Vec::new();
+    for path in run_command::declared_files(&config, &manifest)? {
+        if run_command::file_status(&config, &path)? == Status::WT_NEW {
+            declared_new.push(path.to_str().unwrap().to_string());
+        }
+    }
+
+    // The deployed files that differ from the saved ones.
+    let mut changed = Vec::new();
+    let mut rendered_changed = Vec::new();
+    let mut undeployed = Vec::new();
+    for entry in run_command::host_files(&config, &manifest)? {
+        let target = entry.deployed_path().to_str().unwrap().to_string();
+        match deploy::state(&config, &manifest, entry)? {
+            DeployState::UpToDate => (),
+            DeployState::Changed if entry.template => {
+                rendered_changed.push(target)
+            }
+            DeployState::Changed => changed.push(target),
+            DeployState::Undeployed => undeployed.push(target),
+        }
+    }
+
+    // The copies that differ from the files outside of the worktree.
+    for entry in copies::entries(&config, &manifest)? {
+        let target = entry.deployed_path().to_str().unwrap().to_string();
+        match deploy::state(&config, &manifest, &entry)? {
+            DeployState::UpToDate => (),
+            DeployState::Changed => changed.push(target),
+            DeployState::Undeployed => undeployed.push(target),
+        }
+    }
+
+    // The declared links that are not in place.
+    let mut link_states = Vec::new();
+    for link in manifest.links.iter() {
+        match links::state(&config, link)? {
+            LinkState::UpToDate => (),
+            state => link_states
+                .push((link.target.to_str().unwrap().to_string(), state)),
+        }
+    }
 
-    let status = repo.statuses(Some(&mut status_opts))?;
+    let status: Vec<(String, Status)> = layers::statuses(repo, show_untracked)?
+        .into_iter()
+        .filter(|(path, _)| !declared_new.contains(path))
+        .collect();
 
-    if !status.is_empty() {
+    if !status.is_empty()
+        || !declared_new.is_empty()
+        || !changed.is_empty()
+        || !rendered_changed.is_empty()
+        || !undeployed.is_empty()
+        || !link_states.is_empty()
+    {
         println!("Current status of your configuration:");
-        for entry in status.iter() {
-            match entry.status() {
+        for path in declared_new.iter() {
+            rdm_log::StatusLogger::new_unsaved_file(path);
+        }
+        for path in changed.iter() {
+            rdm_log::StatusLogger::modified_unsaved_file(path);
+        }
+        for path in rendered_changed.iter() {
+            rdm_log::StatusLogger::modified_rendered_file(path);
+        }
+        for path in undeployed.iter() {
+            rdm_log::StatusLogger::undeployed_file(path);
+        }
+        for (path, state) in link_states.iter() {
+            match state {
+                LinkState::Missing => rdm_log::StatusLogger::missing_link(path),
+                LinkState::Broken => rdm_log::StatusLogger::broken_link(path),
+                _ => rdm_log::StatusLogger::foreign_link(path),
+            }
+        }
+        for (path, status) in status.iter() {
+            match *status {
                 Status::CURRENT => {}
                 Status::WT_NEW => {
                     if show_untracked {
-                        rdm_log::StatusLogger::untracked_file(
-                            entry.path().unwrap(),
-                        )
+                        rdm_log::StatusLogger::untracked_file(path)
                     }
                 }
-                Status::INDEX_NEW => {
-                    rdm_log::StatusLogger::new_file(entry.path().unwrap())
-                }
+                Status::INDEX_NEW => rdm_log::StatusLogger::new_file(path),
                 Status::WT_MODIFIED => {
-                    rdm_log::StatusLogger::modified_unsaved_file(
-                        entry.path().unwrap(),
-                    )
+                    rdm_log::StatusLogger::modified_unsaved_file(path)
                 }
                 Status::INDEX_MODIFIED => {
-                    rdm_log::StatusLogger::modified_file(entry.path().unwrap())
+                    rdm_log::StatusLogger::modified_file(path)
                 }
                 Status::WT_DELETED => {
-                    rdm_log::StatusLogger::removed_unsaved_file(
-                        entry.path().unwrap(),
-                    )
+                    rdm_log::StatusLogger::removed_unsaved_file(path)
                 }
                 Status::INDEX_DELETED => {
-                    rdm_log::StatusLogger::removed_file(entry.path().unwrap())
+                    rdm_log::StatusLogger::removed_file(path)
                 }
                 Status::IGNORED => (),
                 _ => {
-                    println!(
-                        "File {} is something else",
-                        entry.path().unwrap()
-                    );
+                    println!("File {} is something else", path);
                 }
             }
         }
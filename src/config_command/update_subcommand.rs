@@ -12,16 +12,34 @@ pub(crate) enum UpdateSubcommandError {
     GitError(git2::Error),
     #[doc_to_string]
     IoError(std::io::Error),
+    #[doc_format(
+        format_str = "The path {} is not in the worktree, add it with `rdm \
+                      config add --copy'.",
+        _1
+    )]
+    #[no_from]
+    OutsideWorktree(String),
 }
 
 fn update_path(
     repo: &git2::Repository,
     index: &mut git2::Index,
     path: &Path,
+    dry_run: bool,
 ) -> Result<(), UpdateSubcommandError> {
     let status = repo.status_file(path)?;
 
-    if status.contains(git2::Status::WT_MODIFIED) {
+    if !status.contains(git2::Status::WT_MODIFIED) {
+        return Ok(());
+    }
+
+    if dry_run {
+        log::info!(
+            "Would update the file or directory {} in your configuration \
+             repository",
+            path.to_str().unwrap()
+        );
+    } else {
         index.add_path(path.as_ref())?;
         index.write()?;
 
@@ -49,14 +67,18 @@ pub(super) fn run(
             .filter(|e| e.status() != git2::Status::CURRENT)
         {
             let path = Path::new(entry.path().unwrap());
-            update_path(&repo, &mut index, path)?;
+            update_path(&repo, &mut index, path, config.dry_run)?;
         }
     } else {
         paths.iter().try_for_each(|path| {
             let abs_path = std::fs::canonicalize(path)?;
             let rel_path =
-                pathdiff::diff_paths(abs_path, &config.worktree_path).unwrap();
-            update_path(&repo, &mut index, rel_path.as_path())
+                abs_path.strip_prefix(&config.worktree_path).map_err(|_| {
+                    UpdateSubcommandError::OutsideWorktree(
+                        path.to_string_lossy().to_string(),
+                    )
+                })?;
+            update_path(&repo, &mut index, rel_path, config.dry_run)
         })?;
     }
 
@@ -0,0 +1,61 @@
+use std::path::{Component, Path, PathBuf};
+
+use rdm_lua::{DirectoryEntry, FileEntry, Manifest};
+
+use crate::config::Config;
+
+/// Directory of the configuration holding the files added with `config add
+/// --copy`, each one under its absolute path.
+const COPIES_DIR: &str = "copies";
+
+/// The directory of the copies added with `config add --copy`, relative to
+/// the worktree. There is none if the configuration is outside of the
+/// worktree.
+pub(crate) fn copies_dir(config: &Config) -> Option<PathBuf> {
+    config
+        .config_path
+        .strip_prefix(&config.worktree_path)
+        .ok()
+        .map(|config_path| config_path.join(COPIES_DIR))
+}
+
+/// Where the copy of the file at the absolute `path` is saved, relative to the
+/// worktree.
+pub(crate) fn mapped_path(copies_dir: &Path, path: &Path) -> PathBuf {
+    copies_dir.join(
+        path.components()
+            .filter(|c| !matches!(c, Component::RootDir | Component::Prefix(_)))
+            .collect::<PathBuf>(),
+    )
+}
+
+/// The copies of the configuration: the ones declared with `copy` and the
+/// ones added with `config add --copy`, deployed to the path they are mapped
+/// from.
+pub(crate) fn entries(
+    config: &Config,
+    manifest: &Manifest,
+) -> Result<Vec<FileEntry>, std::io::Error> {
+    let mut entries = manifest.copies.clone();
+
+    let copies_dir = match copies_dir(config) {
+        Some(dir) if config.worktree_path.join(&dir).is_dir() => dir,
+        _ => return Ok(entries),
+    };
+
+    for path in
+        DirectoryEntry::new(copies_dir.clone()).files(&config.worktree_path)?
+    {
+        if entries.iter().any(|entry| entry.path == path) {
+            continue;
+        }
+
+        let target =
+            Path::new("/").join(path.strip_prefix(&copies_dir).unwrap());
+        let mut entry = FileEntry::new(path);
+        entry.target = Some(target);
+        entries.push(entry);
+    }
+
+    Ok(entries)
+}
@@ -0,0 +1,474 @@
+use std::{
+    collections::BTreeMap,
+    ffi::OsString,
+    fs::OpenOptions,
+    io::Write,
+    os::unix::fs::{OpenOptionsExt, PermissionsExt},
+    path::{Path, PathBuf},
+};
+
+use git2::{
+    ApplyLocation, Diff, DiffOptions, ObjectType, Oid, Patch, Repository,
+};
+use rdm_lua::{FileEntry, Manifest};
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{backup, config::Config, secrets, utils};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "Error while deploying your files:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum DeployError {
+    #[doc_to_string]
+    IoError(std::io::Error),
+    #[doc_to_string]
+    GitError(git2::Error),
+    BackupError(backup::BackupError),
+    SecretError(secrets::SecretError),
+    #[doc_to_string]
+    TemplateError(rdm_lua::TemplateError),
+    #[doc_format(
+        format_str = "The rendered file {} was edited, make the changes to its \
+                      template {} instead or apply them to it with `rdm \
+                      config update --reverse-apply'.",
+        _1,
+        _2
+    )]
+    #[no_from]
+    RenderedFileEdited(String, String),
+    #[doc_format(
+        format_str = "The changes of {} could not be applied to its template \
+                      {}, make them to the template.",
+        _1,
+        _2
+    )]
+    #[no_from]
+    ReverseApplyError(String, String),
+}
+
+/// How the deployed file of an entry differs from the saved one.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum DeployState {
+    UpToDate,
+    /// The target was edited, the next save copies it back to the file.
+    Changed,
+    /// The target is missing, outdated or does not have its mode.
+    Undeployed,
+}
+
+use DeployState::*;
+
+fn deployed_path(repo: &Repository) -> PathBuf {
+    repo.path().join("rdm-deployed")
+}
+
+/// The content last written to each target, or copied from it, by target.
+fn deployed_ids(
+    repo: &Repository,
+) -> Result<BTreeMap<PathBuf, Oid>, DeployError> {
+    let path = deployed_path(repo);
+
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    let mut ids = BTreeMap::new();
+    for line in std::fs::read_to_string(path)?.lines() {
+        if let Some((id, target)) = line.split_once(' ') {
+            ids.insert(PathBuf::from(target), Oid::from_str(id)?);
+        }
+    }
+
+    Ok(ids)
+}
+
+fn set_deployed_id(
+    repo: &Repository,
+    target: &Path,
+    id: Oid,
+) -> Result<(), DeployError> {
+    let mut ids = deployed_ids(repo)?;
+    ids.insert(target.to_path_buf(), id);
+
+    let lines: Vec<String> = ids
+        .iter()
+        .map(|(target, id)| format!("{} {}", id, target.to_str().unwrap()))
+        .collect();
+    utils::write_atomically(&deployed_path(repo), lines.join("\n").as_bytes())?;
+
+    Ok(())
+}
+
+/// Records the current content of `target` as deployed, for a target that
+/// starts being managed with the content of its file.
+pub(crate) fn mark_deployed(
+    config: &Config,
+    target: &Path,
+) -> Result<(), DeployError> {
+    let id =
+        Oid::hash_file(ObjectType::Blob, config.worktree_path.join(target))?;
+    set_deployed_id(&config.repo, target, id)
+}
+
+/// The permissions of the target of `entry`, a secret is only readable by its
+/// owner unless it has its own mode.
+fn target_mode(entry: &FileEntry) -> Option<u32> {
+    entry.mode.or(entry.secret.then_some(0o600))
+}
+
+/// Writes `contents` to `path` through a temporary file created with `mode`,
+/// so that `path` never has other permissions, not even for a moment.
+fn write_with_mode(
+    path: &Path,
+    contents: &[u8],
+    mode: u32,
+) -> Result<(), std::io::Error> {
+    let mut tmp_name = OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(".rdm-tmp");
+    let tmp = path.with_file_name(tmp_name);
+
+    // A file left by an interrupted write may not have the mode.
+    if tmp.exists() {
+        std::fs::remove_file(&tmp)?;
+    }
+
+    let result = OpenOptions::new()
+        .write(true)
+        .create_new(true)
+        .mode(mode)
+        .open(&tmp)
+        .and_then(|mut file| {
+            // The umask may have removed some permissions of `mode`.
+            file.set_permissions(std::fs::Permissions::from_mode(mode))?;
+            file.write_all(contents)
+        })
+        .and_then(|()| std::fs::rename(&tmp, path));
+
+    if result.is_err() {
+        let _ = std::fs::remove_file(&tmp);
+    }
+
+    result
+}
+
+/// The content of the deployed file of `entry`, rendered if it is a template
+/// or decrypted if it is a secret.
+fn contents(
+    config: &Config,
+    manifest: &Manifest,
+    entry: &FileEntry,
+) -> Result<Vec<u8>, DeployError> {
+    let abs_path = config.worktree_path.join(&entry.path);
+
+    if entry.secret {
+        return Ok(secrets::decrypt(
+            config,
+            entry.path.to_str().unwrap(),
+            &std::fs::read(abs_path)?,
+        )?);
+    } else if !entry.template {
+        return Ok(std::fs::read(abs_path)?);
+    }
+
+    let rendered = rdm_lua::render(
+        &std::fs::read_to_string(abs_path)?,
+        entry.path.to_str().unwrap(),
+        manifest,
+        &config.facts,
+    )?;
+
+    Ok(rendered.into_bytes())
+}
+
+pub(crate) fn state(
+    config: &Config,
+    manifest: &Manifest,
+    entry: &FileEntry,
+) -> Result<DeployState, DeployError> {
+    let abs_path = config.worktree_path.join(&entry.path);
+    let target = entry.deployed_path();
+    let abs_target = config.worktree_path.join(target);
+
+    if !abs_target.is_file() {
+        return Ok(if abs_path.is_file() && entry.is_deployed() {
+            Undeployed
+        } else {
+            UpToDate
+        });
+    }
+
+    if entry.is_deployed() {
+        if !abs_path.is_file() {
+            return Ok(if entry.template { UpToDate } else { Changed });
+        }
+
+        let deployed = std::fs::read(&abs_target)?;
+        if deployed != contents(config, manifest, entry)? {
+            // The target still holds what was last deployed when only the file
+            // was edited.
+            let id = Oid::hash_object(ObjectType::Blob, &deployed)?;
+            return Ok(match deployed_ids(&config.repo)?.get(target) {
+                Some(deployed_id) if *deployed_id == id => Undeployed,
+                // A template replaces the files it was never rendered to.
+                None if entry.template => Undeployed,
+                _ => Changed,
+            });
+        }
+    }
+
+    match target_mode(entry) {
+        Some(mode)
+            if abs_target.metadata()?.permissions().mode() & 0o7777 != mode =>
+        {
+            Ok(Undeployed)
+        }
+        _ => Ok(UpToDate),
+    }
+}
+
+/// Applies the edits made to the rendered file of the template `entry` to the
+/// template, as a patch.
+fn reverse_apply(
+    config: &Config,
+    manifest: &Manifest,
+    entry: &FileEntry,
+) -> Result<(), DeployError> {
+    let target = entry.deployed_path();
+    let error = || {
+        DeployError::ReverseApplyError(
+            target.to_string_lossy().to_string(),
+            entry.path.to_string_lossy().to_string(),
+        )
+    };
+
+    // The edits are only known if the template still renders to what was
+    // deployed.
+    let rendered = contents(config, manifest, entry)?;
+    let rendered_id = Oid::hash_object(ObjectType::Blob, &rendered)?;
+    if deployed_ids(&config.repo)?.get(target) != Some(&rendered_id) {
+        return Err(error());
+    }
+
+    let edited = std::fs::read(config.worktree_path.join(target))?;
+    let mut opts = DiffOptions::new();
+    opts.context_lines(0);
+    let mut patch = Patch::from_buffers(
+        &rendered,
+        Some(&entry.path),
+        &edited,
+        Some(&entry.path),
+        Some(&mut opts),
+    )?;
+    let diff = Diff::from_buffer(&patch.to_buf()?)?;
+
+    if config.dry_run {
+        log::info!(
+            "Would apply the changes of {} to {}.",
+            target.to_str().unwrap(),
+            entry.path.to_str().unwrap()
+        );
+        return Ok(());
+    }
+
+    config
+        .repo
+        .apply(&diff, ApplyLocation::WorkDir, None)
+        .map_err(|_| error())?;
+
+    let rendered = contents(config, manifest, entry)?;
+    set_deployed_id(
+        &config.repo,
+        target,
+        Oid::hash_object(ObjectType::Blob, &rendered)?,
+    )?;
+
+    log::info!(
+        "Applied the changes of {} to {}.",
+        target.to_str().unwrap(),
+        entry.path.to_str().unwrap()
+    );
+
+    Ok(())
+}
+
+/// Encrypts the target of the secret `entry` to its file.
+fn encrypt(config: &Config, entry: &FileEntry) -> Result<(), DeployError> {
+    let target = entry.deployed_path();
+
+    if config.dry_run {
+        log::info!(
+            "Would encrypt the changes of {} to {}.",
+            target.to_str().unwrap(),
+            entry.path.to_str().unwrap()
+        );
+        return Ok(());
+    }
+
+    let abs_target = config.worktree_path.join(target);
+    // The edited plaintext is restricted before anything else is done with it,
+    // it may have been saved with looser permissions.
+    if let Some(mode) = target_mode(entry) {
+        std::fs::set_permissions(
+            &abs_target,
+            std::fs::Permissions::from_mode(mode),
+        )?;
+    }
+    let plaintext = std::fs::read(&abs_target)?;
+    std::fs::write(
+        config.worktree_path.join(&entry.path),
+        secrets::encrypt(config, &plaintext)?,
+    )?;
+    set_deployed_id(
+        &config.repo,
+        target,
+        Oid::hash_object(ObjectType::Blob, &plaintext)?,
+    )?;
+
+    log::info!(
+        "Encrypted the changes of {} to {}.",
+        target.to_str().unwrap(),
+        entry.path.to_str().unwrap()
+    );
+
+    Ok(())
+}
+
+/// Copies the target of `entry` back to its file if it was edited since it was
+/// deployed, encrypted if it is a secret. The file is backed up if it has its
+/// own changes. The edits of a rendered template are refused, unless `reverse`
+/// is set, then they are applied to the template.
+pub(crate) fn collect(
+    config: &Config,
+    manifest: &Manifest,
+    entry: &FileEntry,
+    reverse: bool,
+) -> Result<(), DeployError> {
+    if state(config, manifest, entry)? != Changed {
+        return Ok(());
+    }
+
+    let target = entry.deployed_path();
+    let abs_path = config.worktree_path.join(&entry.path);
+    let abs_target = config.worktree_path.join(target);
+
+    if entry.template && reverse {
+        return reverse_apply(config, manifest, entry);
+    } else if entry.template {
+        return Err(DeployError::RenderedFileEdited(
+            target.to_string_lossy().to_string(),
+            entry.path.to_string_lossy().to_string(),
+        ));
+    }
+
+    if entry.secret {
+        return encrypt(config, entry);
+    }
+
+    if config.dry_run {
+        log::info!(
+            "Would copy the changes of {} to {}.",
+            target.to_str().unwrap(),
+            entry.path.to_str().unwrap()
+        );
+        return Ok(());
+    }
+
+    if abs_path.is_file() {
+        let id = Oid::hash_file(ObjectType::Blob, &abs_path)?;
+        if deployed_ids(&config.repo)?.get(target) != Some(&id) {
+            backup::create(
+                &config.repo,
+                &config.config_path,
+                std::slice::from_ref(&entry.path),
+                "collecting",
+            )?;
+        }
+    }
+
+    if let Some(parent) = abs_path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::copy(&abs_target, &abs_path)?;
+    set_deployed_id(
+        &config.repo,
+        target,
+        Oid::hash_file(ObjectType::Blob, &abs_target)?,
+    )?;
+
+    log::info!(
+        "Copied the changes of {} to {}.",
+        target.to_str().unwrap(),
+        entry.path.to_str().unwrap()
+    );
+
+    Ok(())
+}
+
+/// Writes the file of `entry` to its target and enforces its mode. A target
+/// that was not written by rdm is backed up before being replaced.
+pub(crate) fn deploy(
+    config: &Config,
+    manifest: &Manifest,
+    entry: &FileEntry,
+) -> Result<(), DeployError> {
+    let target = entry.deployed_path();
+    let abs_target = config.worktree_path.join(target);
+
+    let state = state(config, manifest, entry)?;
+    if !config.worktree_path.join(&entry.path).is_file() || state == UpToDate {
+        return Ok(());
+    }
+
+    if config.dry_run {
+        log::info!(
+            "Would deploy {} to {}.",
+            entry.path.to_str().unwrap(),
+            target.to_str().unwrap()
+        );
+        return Ok(());
+    }
+
+    if entry.is_deployed() {
+        let contents = contents(config, manifest, entry)?;
+        let id = Oid::hash_object(ObjectType::Blob, &contents)?;
+
+        if abs_target.is_file() {
+            let target_id = Oid::hash_file(ObjectType::Blob, &abs_target)?;
+            if target_id != id
+                && deployed_ids(&config.repo)?.get(target) != Some(&target_id)
+            {
+                backup::create(
+                    &config.repo,
+                    &config.config_path,
+                    &[target.to_path_buf()],
+                    "deploying",
+                )?;
+            }
+        }
+        if let Some(parent) = abs_target.parent() {
+            std::fs::create_dir_all(parent)?;
+        }
+
+        match target_mode(entry) {
+            Some(mode) => write_with_mode(&abs_target, &contents, mode)?,
+            None => std::fs::write(&abs_target, &contents)?,
+        }
+        set_deployed_id(&config.repo, target, id)?;
+    }
+
+    if let Some(mode) = target_mode(entry) {
+        std::fs::set_permissions(
+            &abs_target,
+            std::fs::Permissions::from_mode(mode),
+        )?;
+    }
+
+    log::info!(
+        "Deployed {} to {}.",
+        entry.path.to_str().unwrap(),
+        target.to_str().unwrap()
+    );
+
+    Ok(())
+}
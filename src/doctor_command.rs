@@ -0,0 +1,328 @@
+use std::path::{Path, PathBuf};
+
+use console::style;
+use gethostname::gethostname;
+use git2::Repository;
+use mlua::MultiValue;
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{config, hosts, lockfile::TomlConfig, utils};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "Error while running the `doctor' command:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum DoctorError {
+    #[doc_format(format_str = "{} check(s) failed.", _1)]
+    #[no_from]
+    FailedChecks(usize),
+    #[doc_to_string]
+    IoError(std::io::Error),
+    #[doc_to_string]
+    ExpandError(utils::ExpandError),
+}
+
+/// How to fix a check that did not pass.
+#[derive(Debug, ToDoc)]
+#[doc_prefix = "hint:"]
+enum Hint {
+    #[doc_format(
+        format_str = "There is no lockfile in `{}', run `rdm init' or `rdm \
+                      clone' to create it, or use `--config-path' if your \
+                      configuration is somewhere else.",
+        _1
+    )]
+    NoLockFile(String),
+    #[doc_format(
+        format_str = "Fix the syntax of the lockfile or restore it with `rdm \
+                      init': {}",
+        _1
+    )]
+    InvalidLockFile(String),
+    #[doc_format(
+        format_str = "Fix `repo_path' in the lockfile, or run `rdm clone' to \
+                      recreate the repository: {}",
+        _1
+    )]
+    InvalidRepo(String),
+    #[doc_text = "Fix `worktree_path' in the lockfile, it is usually `~'."]
+    NoWorktree,
+    #[doc_format(
+        format_str = "Check out the configuration of this host with `rdm \
+                      host switch {}', or create it with `rdm host create {}'.",
+        _1,
+        _1
+    )]
+    HeadNotBranch(String),
+    #[doc_format(
+        format_str = "This machine is `{}', switch to its configuration with \
+                      `rdm host switch {}' or rename the host with `rdm host \
+                      rename {} {}'.",
+        _1,
+        _1,
+        _2,
+        _1
+    )]
+    OtherHost(String, String),
+    #[doc_text = "Create it with `rdm init', your configuration needs it."]
+    NoInitLua,
+    #[doc_format(format_str = "Fix the Lua error: {}", _1)]
+    LuaError(String),
+    #[doc_text = "Add a remote with `rdm config remote add <name> <url> \
+                  --default' to push and pull your configuration."]
+    NoRemote,
+    #[doc_text = "Choose the remote to push and pull with `rdm config remote \
+                  default <name>'."]
+    NoDefaultRemote,
+    #[doc_text = "Set your identity with `git config --global user.name \
+                  <name>' and `git config --global user.email <email>'."]
+    NoIdentity,
+    #[doc_format(
+        format_str = "Hide the untracked files of your worktree with `git \
+                      --git-dir {} config status.showUntrackedFiles no'.",
+        _1
+    )]
+    ShowUntrackedFiles(String),
+}
+
+enum Outcome {
+    Pass,
+    Warn(Hint),
+    Fail(Hint),
+}
+
+/// Prints the result of the check `name` and returns `false` if it failed.
+fn report(name: &str, outcome: Outcome) -> bool {
+    let failed = matches!(outcome, Outcome::Fail(_));
+    let (label, hint) = match outcome {
+        Outcome::Pass => (style("[PASS]").green(), None),
+        Outcome::Warn(hint) => (style("[WARN]").yellow(), Some(hint)),
+        Outcome::Fail(hint) => (style("[FAIL]").red(), Some(hint)),
+    };
+
+    println!("{} {}", label, name);
+
+    if let Some(hint) = hint {
+        let (_, cols) = console::Term::stdout().size();
+        let doc =
+            pretty::RcDoc::<()>::text("       ").append(hint.to_doc().nest(7));
+        let mut buf = Vec::new();
+        if doc.render(cols.into(), &mut buf).is_ok() {
+            println!("{}", String::from_utf8_lossy(&buf));
+        }
+    }
+
+    !failed
+}
+
+fn check_lua_file(path: &Path) -> Outcome {
+    let res = std::fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|str| {
+            let lua = mlua::Lua::new();
+            lua.load(&str)
+                .set_name(path.to_str().unwrap())
+                .and_then(|chunk| chunk.into_function())
+                .map(|_| ())
+                .map_err(|err| err.to_string())
+        });
+
+    match res {
+        Ok(()) => Outcome::Pass,
+        Err(err) => Outcome::Fail(Hint::LuaError(err)),
+    }
+}
+
+/// Evaluates `init.lua` into its manifest, as rdm does. Its scripts and
+/// downloads raise an error instead of being run, so that checking it changes
+/// nothing.
+fn check_init_lua(
+    path: &Path,
+    worktree_path: PathBuf,
+    host: Option<String>,
+    cache: rdm_lua::DownloadCache,
+) -> Outcome {
+    let evaluate = |source: &str| -> Result<(), rdm_lua::RuntimeError> {
+        let facts = rdm_lua::Facts::gather(host, env!("CARGO_PKG_VERSION"));
+        let lua = rdm_lua::init(worktree_path, facts, cache)?;
+
+        for name in ["run_script", "curl"] {
+            let disabled = lua.create_function(move |_, _: MultiValue| {
+                Err::<(), _>(mlua::Error::external(format!(
+                    "`{}' is not run by `rdm doctor', call it from \
+                     `bootstrap.lua'.",
+                    name
+                )))
+            })?;
+            lua.globals().set(name, disabled)?;
+        }
+
+        rdm_lua::evaluate(&lua, source, path.to_str().unwrap())
+            .map_err(rdm_lua::RuntimeError::from_lua)?;
+
+        Ok(())
+    };
+
+    let res = std::fs::read_to_string(path)
+        .map_err(|err| err.to_string())
+        .and_then(|str| {
+            evaluate(&str).map_err(|err| err.to_doc().pretty(80).to_string())
+        });
+
+    match res {
+        Ok(()) => Outcome::Pass,
+        Err(err) => Outcome::Fail(Hint::LuaError(err)),
+    }
+}
+
+fn check_head(repo: &Repository) -> Outcome {
+    let host = gethostname().to_string_lossy().to_string();
+
+    match hosts::current_host(repo) {
+        Ok(Some(current)) if current == host => Outcome::Pass,
+        Ok(Some(current)) => Outcome::Warn(Hint::OtherHost(host, current)),
+        _ => Outcome::Fail(Hint::HeadNotBranch(host)),
+    }
+}
+
+fn check_remotes(repo: &Repository) -> Outcome {
+    match repo.remotes() {
+        Ok(remotes) if !remotes.is_empty() => (),
+        _ => return Outcome::Warn(Hint::NoRemote),
+    }
+
+    match hosts::current_host(repo) {
+        Ok(Some(host)) => match hosts::host_remote(repo, &host) {
+            Ok(Some(_)) => Outcome::Pass,
+            _ => Outcome::Warn(Hint::NoDefaultRemote),
+        },
+        _ => Outcome::Warn(Hint::NoDefaultRemote),
+    }
+}
+
+fn check_untracked_files(repo: &Repository) -> Outcome {
+    let value = repo
+        .config()
+        .and_then(|config| config.get_string("status.showUntrackedFiles"));
+
+    match value {
+        Ok(value) if value == "no" => Outcome::Pass,
+        _ => Outcome::Warn(Hint::ShowUntrackedFiles(
+            repo.path().to_string_lossy().to_string(),
+        )),
+    }
+}
+
+pub(crate) fn run(config_path: Option<String>) -> Result<(), DoctorError> {
+    let config_path = config::config_dir(&config_path)?;
+    let lock_path = config_path.join("rdm.lock");
+    let mut failed = 0;
+    let mut check = |name: &str, outcome| {
+        if !report(name, outcome) {
+            failed += 1;
+        }
+    };
+
+    let lock_paths = if !lock_path.exists() {
+        check(
+            "The lockfile exists",
+            Outcome::Fail(Hint::NoLockFile(
+                config_path.to_string_lossy().to_string(),
+            )),
+        );
+        None
+    } else {
+        let str = std::fs::read_to_string(&lock_path)?;
+        // The paths of the lockfile are expanded with the environment.
+        let paths = TomlConfig::parse(&str).and_then(|(lock, _)| {
+            Ok((lock.repo_path()?, lock.worktree_path()?))
+        });
+        match paths {
+            Ok(paths) => {
+                check("The lockfile parses", Outcome::Pass);
+                Some(paths)
+            }
+            Err(err) => {
+                check(
+                    "The lockfile parses",
+                    Outcome::Fail(Hint::InvalidLockFile(
+                        err.to_doc().pretty(80).to_string(),
+                    )),
+                );
+                None
+            }
+        }
+    };
+
+    // Where `init.lua' is evaluated, and for which host.
+    let mut init_context = None;
+
+    if let Some((repo_path, worktree_path)) = lock_paths {
+        check(
+            "The worktree exists",
+            if worktree_path.is_dir() {
+                Outcome::Pass
+            } else {
+                Outcome::Fail(Hint::NoWorktree)
+            },
+        );
+
+        match Repository::open_bare(repo_path) {
+            Ok(repo) if repo.is_bare() => {
+                init_context = Some((
+                    worktree_path,
+                    hosts::current_host(&repo).ok().flatten(),
+                ));
+                check("The repository is a bare repository", Outcome::Pass);
+                check("HEAD is on the branch of this host", check_head(&repo));
+                check("A default remote is configured", check_remotes(&repo));
+                check(
+                    "A commit identity is set",
+                    match repo.signature() {
+                        Ok(_) => Outcome::Pass,
+                        Err(_) => Outcome::Fail(Hint::NoIdentity),
+                    },
+                );
+                check(
+                    "Untracked files are hidden",
+                    check_untracked_files(&repo),
+                );
+            }
+            Ok(_) => check(
+                "The repository is a bare repository",
+                Outcome::Fail(Hint::InvalidRepo(
+                    "the repository is not bare".to_string(),
+                )),
+            ),
+            Err(err) => check(
+                "The repository is a bare repository",
+                Outcome::Fail(Hint::InvalidRepo(err.message().to_string())),
+            ),
+        }
+    }
+
+    let init_path = config_path.join("init.lua");
+    check(
+        "`init.lua' loads",
+        match init_context {
+            _ if !init_path.exists() => Outcome::Fail(Hint::NoInitLua),
+            Some((worktree_path, host)) => {
+                let cache =
+                    rdm_lua::DownloadCache::new(config::cache_path()?, true);
+                check_init_lua(&init_path, worktree_path, host, cache)
+            }
+            // Without a repository, only the syntax is checked.
+            None => check_lua_file(&init_path),
+        },
+    );
+
+    let bootstrap_path = config_path.join("bootstrap.lua");
+    if bootstrap_path.exists() {
+        check("`bootstrap.lua' loads", check_lua_file(&bootstrap_path));
+    }
+
+    if failed > 0 {
+        Err(DoctorError::FailedChecks(failed))
+    } else {
+        Ok(())
+    }
+}
@@ -0,0 +1,53 @@
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{
+    config::Config, config_command::remote_commands::default_subcommand, hosts,
+};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "create error:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum CreateError {
+    #[doc_to_string]
+    GitError(git2::Error),
+    #[doc_format(format_str = "The host {} already exists.", _1)]
+    #[no_from]
+    AlreadyExists(String),
+    #[doc_format(format_str = "The host {} does not exist.", _1)]
+    #[no_from]
+    NotFound(String),
+    #[doc_text = "HEAD is not on a host branch, use `--from' to choose one."]
+    HeadNoBranch,
+}
+
+pub(super) fn run(
+    config: Config,
+    name: String,
+    from: Option<String>,
+) -> Result<(), CreateError> {
+    let repo = config.repo;
+
+    if hosts::find_host(&repo, &name)?.is_some() {
+        return Err(CreateError::AlreadyExists(name));
+    }
+
+    let from = match from {
+        Some(from) => from,
+        None => hosts::current_host(&repo)?.ok_or(CreateError::HeadNoBranch)?,
+    };
+
+    let source = match hosts::find_host(&repo, &from)? {
+        Some(source) => source,
+        None => return Err(CreateError::NotFound(from)),
+    };
+
+    let commit = source.get().peel_to_commit()?;
+    repo.branch(&name, &commit, false)?;
+    log::info!("The host {} was created from {}", name, from);
+
+    if let Some(remote) = hosts::host_remote(&repo, &from)? {
+        default_subcommand::set_branch_remote(&repo, &name, &remote)?;
+    }
+
+    Ok(())
+}
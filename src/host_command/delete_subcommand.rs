@@ -0,0 +1,40 @@
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{config::Config, hosts};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "delete error:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum DeleteError {
+    #[doc_to_string]
+    GitError(git2::Error),
+    #[doc_format(format_str = "The host {} does not exist.", _1)]
+    #[no_from]
+    NotFound(String),
+    #[doc_format(
+        format_str = "The host {} is the current one, switch to another \
+                      host before deleting it.",
+        _1
+    )]
+    #[no_from]
+    IsCurrent(String),
+}
+
+pub(super) fn run(config: Config, name: String) -> Result<(), DeleteError> {
+    let repo = config.repo;
+
+    if hosts::current_host(&repo)?.as_deref() == Some(name.as_str()) {
+        return Err(DeleteError::IsCurrent(name));
+    }
+
+    let mut branch = match hosts::find_host(&repo, &name)? {
+        Some(branch) => branch,
+        None => return Err(DeleteError::NotFound(name)),
+    };
+
+    branch.delete()?;
+    hosts::remove_host_remote(&repo, &name)?;
+    log::info!("The host {} was deleted", name);
+
+    Ok(())
+}
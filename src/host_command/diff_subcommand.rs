@@ -0,0 +1,126 @@
+use std::path::PathBuf;
+
+use console::style;
+use git2::{Delta, Diff, DiffFormat, DiffOptions, DiffStatsFormat, Tree};
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{config::Config, hosts, layers, utils};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "diff error:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum DiffError {
+    #[doc_to_string]
+    GitError(git2::Error),
+    #[doc_to_string]
+    IoError(std::io::Error),
+    #[doc_format(format_str = "The host {} does not exist.", _1)]
+    #[no_from]
+    NotFound(String),
+}
+
+/// The files of `host` on top of the shared layer. The groups are left out as
+/// only the ones of the current host are known.
+fn host_tree<'r>(
+    repo: &'r git2::Repository,
+    host: &str,
+) -> Result<Tree<'r>, DiffError> {
+    let branch = match hosts::find_host(repo, host)? {
+        Some(branch) => branch,
+        None => return Err(DiffError::NotFound(host.to_string())),
+    };
+    let tree = branch.get().peel_to_tree()?;
+
+    match layers::layer_tree(repo, &layers::shared_ref(repo)?)? {
+        Some(shared) => Ok(layers::overlay(repo, &shared, &tree)?),
+        None => Ok(tree),
+    }
+}
+
+fn delta_path(delta: &git2::DiffDelta) -> String {
+    delta
+        .new_file()
+        .path()
+        .or_else(|| delta.old_file().path())
+        .map(|path| path.to_string_lossy().into_owned())
+        .unwrap_or_default()
+}
+
+fn print_only_in(diff: &Diff, host_a: &str, host_b: &str) {
+    for delta in diff.deltas() {
+        match delta.status() {
+            Delta::Deleted => println!(
+                "{} {}",
+                style(format!("Only in {}:", host_a)).red(),
+                delta_path(&delta)
+            ),
+            Delta::Added => println!(
+                "{} {}",
+                style(format!("Only in {}:", host_b)).green(),
+                delta_path(&delta)
+            ),
+            _ => (),
+        }
+    }
+}
+
+fn print_patch(diff: &Diff) -> Result<(), git2::Error> {
+    diff.print(DiffFormat::Patch, |delta, _, line| {
+        if matches!(delta.status(), Delta::Added | Delta::Deleted) {
+            return true;
+        }
+
+        let content = String::from_utf8_lossy(line.content());
+        match line.origin() {
+            '+' | '-' | ' ' => {
+                let text = format!("{}{}", line.origin(), content);
+                match line.origin() {
+                    '+' => print!("{}", style(text).green()),
+                    '-' => print!("{}", style(text).red()),
+                    _ => print!("{}", text),
+                }
+            }
+            'F' => print!("{}", style(content).bold()),
+            'H' => print!("{}", style(content).cyan()),
+            _ => print!("{}", content),
+        }
+        true
+    })
+}
+
+pub(super) fn run(
+    config: Config,
+    host_a: String,
+    host_b: String,
+    paths: Vec<PathBuf>,
+    stat: bool,
+) -> Result<(), DiffError> {
+    let repo = &config.repo;
+
+    let tree_a = host_tree(repo, &host_a)?;
+    let tree_b = host_tree(repo, &host_b)?;
+
+    let mut opts = DiffOptions::new();
+    for path in paths {
+        opts.pathspec(utils::worktree_relative(&path, &config.worktree_path)?);
+    }
+
+    let diff =
+        repo.diff_tree_to_tree(Some(&tree_a), Some(&tree_b), Some(&mut opts))?;
+
+    if diff.deltas().len() == 0 {
+        println!("{} and {} have the same configuration.", host_a, host_b);
+        return Ok(());
+    }
+
+    if stat {
+        let (_, cols) = console::Term::stdout().size();
+        let stats = diff.stats()?.to_buf(DiffStatsFormat::FULL, cols.into())?;
+        print!("{}", stats.as_str().unwrap_or_default());
+    } else {
+        print_only_in(&diff, &host_a, &host_b);
+        print_patch(&diff)?;
+    }
+
+    Ok(())
+}
@@ -0,0 +1,64 @@
+use chrono::{DateTime, Local};
+use pretty::RcDoc;
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{config::Config, hosts};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "list error:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum ListError {
+    #[doc_to_string]
+    GitError(git2::Error),
+    #[doc_to_string]
+    IoError(std::io::Error),
+    #[doc_to_string]
+    ToString(std::string::FromUtf8Error),
+}
+
+pub(super) fn run(config: Config) -> Result<(), ListError> {
+    let repo = config.repo;
+    let current = hosts::current_host(&repo)?;
+
+    let mut lines = Vec::new();
+    for (name, branch) in hosts::host_branches(&repo)? {
+        let commit = branch.get().peel_to_commit()?;
+        let date = DateTime::from_timestamp(commit.time().seconds(), 0)
+            .map(|date| {
+                date.with_timezone(&Local)
+                    .format("%Y-%m-%d %H:%M")
+                    .to_string()
+            })
+            .unwrap_or_default();
+        let marker = if current.as_deref() == Some(name.as_str()) {
+            "*"
+        } else {
+            " "
+        };
+        let remote = match hosts::host_remote(&repo, &name)? {
+            Some(remote) => format!(" -> {}", remote),
+            None => String::new(),
+        };
+
+        lines.push(RcDoc::text(format!(
+            "{} {}{}: {} ({})",
+            marker,
+            name,
+            remote,
+            commit.summary().unwrap_or(""),
+            date
+        )));
+    }
+
+    let doc = RcDoc::<()>::text("Available hosts:").append(
+        RcDoc::line()
+            .append(RcDoc::intersperse(lines, RcDoc::line()))
+            .nest(1),
+    );
+    let mut buf = Vec::new();
+    let (_, cols) = console::Term::stdout().size();
+    doc.render(cols.into(), &mut buf)?;
+    let str = String::from_utf8(buf)?;
+    println!("{}", str);
+    Ok(())
+}
@@ -0,0 +1,60 @@
+mod create_subcommand;
+mod delete_subcommand;
+mod diff_subcommand;
+mod list_subcommand;
+mod rename_subcommand;
+mod switch_subcommand;
+
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{
+    args::HostSubCommand,
+    config::{self, Config},
+};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "Host Command Error:"]
+pub(crate) enum HostCommandError {
+    ListError(list_subcommand::ListError),
+    CreateError(create_subcommand::CreateError),
+    SwitchError(switch_subcommand::SwitchError),
+    RenameError(rename_subcommand::RenameError),
+    DeleteError(delete_subcommand::DeleteError),
+    DiffError(diff_subcommand::DiffError),
+    ConfigError(config::ConfigError),
+}
+
+pub(crate) fn run(
+    sub_command: HostSubCommand,
+    config: Config,
+) -> Result<(), HostCommandError> {
+    // Only `list` and `diff` leave the repository and the worktree alone.
+    let _lock = match sub_command {
+        HostSubCommand::List | HostSubCommand::Diff { .. } => None,
+        _ => Some(config.lock()?),
+    };
+
+    match sub_command {
+        HostSubCommand::List => list_subcommand::run(config)?,
+        HostSubCommand::Create { name, from } => {
+            create_subcommand::run(config, name, from)?
+        }
+        HostSubCommand::Switch { name, force } => {
+            switch_subcommand::run(config, name, force)?
+        }
+        HostSubCommand::Rename { old, new } => {
+            rename_subcommand::run(config, old, new)?
+        }
+        HostSubCommand::Delete { name } => {
+            delete_subcommand::run(config, name)?
+        }
+        HostSubCommand::Diff {
+            host_a,
+            host_b,
+            paths,
+            stat,
+        } => diff_subcommand::run(config, host_a, host_b, paths, stat)?,
+    };
+
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{
+    config::Config, config_command::remote_commands::default_subcommand, hosts,
+};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "rename error:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum RenameError {
+    #[doc_to_string]
+    GitError(git2::Error),
+    #[doc_format(format_str = "The host {} does not exist.", _1)]
+    #[no_from]
+    NotFound(String),
+    #[doc_format(format_str = "The host {} already exists.", _1)]
+    #[no_from]
+    AlreadyExists(String),
+}
+
+pub(super) fn run(
+    config: Config,
+    old: String,
+    new: String,
+) -> Result<(), RenameError> {
+    let repo = config.repo;
+
+    if hosts::find_host(&repo, &new)?.is_some() {
+        return Err(RenameError::AlreadyExists(new));
+    }
+
+    let mut branch = match hosts::find_host(&repo, &old)? {
+        Some(branch) => branch,
+        None => return Err(RenameError::NotFound(old)),
+    };
+
+    let remote = hosts::host_remote(&repo, &old)?;
+
+    branch.rename(&new, false)?;
+    log::info!("The host {} was renamed to {}", old, new);
+
+    hosts::remove_host_remote(&repo, &old)?;
+    if let Some(remote) = remote {
+        default_subcommand::set_branch_remote(&repo, &new, &remote)?;
+    }
+
+    Ok(())
+}
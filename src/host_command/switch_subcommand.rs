@@ -0,0 +1,73 @@
+use git2::build::CheckoutBuilder;
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{
+    backup,
+    config::Config,
+    hosts, layers,
+    lockfile::{self, TomlConfig},
+    run_command,
+};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "switch error:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum SwitchError {
+    #[doc_to_string]
+    GitError(git2::Error),
+    BackupError(backup::BackupError),
+    LockFileError(lockfile::LockFileError),
+    #[doc_format(format_str = "The host {} does not exist.", _1)]
+    #[no_from]
+    NotFound(String),
+    #[doc_text = "Your configuration has unsaved changes, save them or use \
+                  `--force' to discard them."]
+    UnsavedChanges,
+    RunError(run_command::RunCommandError),
+}
+
+pub(super) fn run(
+    config: Config,
+    name: String,
+    force: bool,
+) -> Result<(), SwitchError> {
+    let repo = &config.repo;
+
+    let branch = match hosts::find_host(repo, &name)? {
+        Some(branch) => branch,
+        None => return Err(SwitchError::NotFound(name)),
+    };
+
+    let unsaved = !layers::statuses(repo, false)?.is_empty();
+    if unsaved && !force {
+        return Err(SwitchError::UnsavedChanges);
+    }
+
+    let mut lock_path = config.config_path.clone();
+    lock_path.push("rdm.lock");
+
+    // The lockfile describes this machine, it must survive the switch.
+    let local_lock = TomlConfig::load(&lock_path, false)?;
+
+    let tree = branch.get().peel_to_tree()?;
+    layers::checkout(
+        repo,
+        &config.config_path,
+        &tree,
+        &format!("switching to {}", name),
+        &mut CheckoutBuilder::default(),
+    )?;
+    repo.set_head(branch.get().name().unwrap())?;
+
+    local_lock.save(&lock_path)?;
+
+    // The host may belong to other groups, their files are checked out
+    // before deploying.
+    let manifest = run_command::load(&config)?;
+    run_command::apply_groups(&config, &manifest)?;
+    run_command::deploy(&config, &manifest)?;
+
+    log::info!("Switched to the configuration of {}", name);
+
+    Ok(())
+}
@@ -0,0 +1,82 @@
+use git2::{Branch, BranchType, Repository};
+
+use crate::layers;
+
+/// Returns the local branches holding the configuration of a host, with their
+/// name. The branches of the shared layer and of the groups are not hosts.
+pub(crate) fn host_branches(
+    repo: &Repository,
+) -> Result<Vec<(String, Branch<'_>)>, git2::Error> {
+    let shared = layers::shared_branch(repo)?;
+    let mut hosts = Vec::new();
+
+    for branch in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = branch?;
+        if let Some(name) = branch.name()? {
+            if name != shared && !name.starts_with(layers::GROUP_PREFIX) {
+                hosts.push((name.to_string(), branch));
+            }
+        }
+    }
+
+    hosts.sort_by(|(a, _), (b, _)| a.cmp(b));
+
+    Ok(hosts)
+}
+
+pub(crate) fn find_host<'r>(
+    repo: &'r Repository,
+    name: &str,
+) -> Result<Option<Branch<'r>>, git2::Error> {
+    Ok(host_branches(repo)?
+        .into_iter()
+        .find(|(host, _)| host == name)
+        .map(|(_, branch)| branch))
+}
+
+/// Returns the name of the host branch HEAD is on, if any.
+pub(crate) fn current_host(
+    repo: &Repository,
+) -> Result<Option<String>, git2::Error> {
+    let head = repo.head()?;
+
+    if head.is_branch() {
+        Ok(head.shorthand().map(String::from))
+    } else {
+        Ok(None)
+    }
+}
+
+/// Returns the remote configured for the branch of `host`, if any.
+pub(crate) fn host_remote(
+    repo: &Repository,
+    host: &str,
+) -> Result<Option<String>, git2::Error> {
+    let config = repo.config()?.snapshot()?;
+    let remote_key = format!("branch.{}.remote", host);
+
+    match config.get_string(&remote_key) {
+        Ok(remote) => Ok(Some(remote)),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Removes the `branch.<host>.*` settings of `host`.
+pub(crate) fn remove_host_remote(
+    repo: &Repository,
+    host: &str,
+) -> Result<(), git2::Error> {
+    let mut config = repo.config()?;
+
+    for key in ["remote", "merge"] {
+        match config.remove(&format!("branch.{}.{}", host, key)) {
+            Err(err) if err.code() != git2::ErrorCode::NotFound => {
+                return Err(err)
+            }
+            _ => (),
+        }
+    }
+
+    Ok(())
+}
@@ -5,8 +5,11 @@ use git2::build::CheckoutBuilder;
 use git2::{Repository, RepositoryInitOptions};
 use rdm_macros::{FromError, ToDoc};
 
+use crate::backup;
+use crate::config;
 use crate::lockfile;
 use crate::lockfile::TomlConfig;
+use crate::utils;
 
 #[derive(Debug, FromError, ToDoc)]
 #[doc_prefix = "Error while running the `init' command:"]
@@ -31,20 +34,19 @@ pub(crate) enum InitError {
     LockFileError(lockfile::LockFileError),
     #[doc_to_string]
     EnvError(std::env::VarError),
+    #[doc_to_string]
+    ExpandError(utils::ExpandError),
+    BackupError(backup::BackupError),
 }
 
 pub(crate) fn run(
     repo_path: Option<String>,
     config_path: Option<String>,
     worktree: Option<String>,
+    config_dir: Option<String>,
 ) -> Result<(), InitError> {
     let repo_path_buf = match &repo_path {
-        None => {
-            let home = std::env::var("HOME")?;
-            let mut home_path = PathBuf::from(home);
-            home_path.push(".config/rdm/repo");
-            home_path
-        }
+        None => config::default_repo_path()?,
         Some(path) => PathBuf::from(path),
     };
 
@@ -63,12 +65,7 @@ pub(crate) fn run(
 
     let config_path_buf = match &config_path {
         Some(path) => PathBuf::from(path),
-        None => {
-            let home = std::env!("HOME");
-            let mut path = PathBuf::from(home);
-            path.push(".config/rdm/rdm.lua");
-            path
-        }
+        None => config::config_dir(&config_dir)?.join("init.lua"),
     };
 
     match config_path_buf.extension() {
@@ -130,7 +127,7 @@ pub(crate) fn run(
     gitgnore_path.pop();
 
     let rel_repo_path =
-        pathdiff::diff_paths(repo_path_buf, &gitgnore_path).unwrap();
+        pathdiff::diff_paths(&repo_path_buf, &gitgnore_path).unwrap();
     writeln!(&mut gitignore, "{}", rel_repo_path.display())?;
 
     repo.config()?
@@ -150,15 +147,13 @@ pub(crate) fn run(
             path.push("rdm.lock");
             path
         }
-        None => {
-            let home = std::env!("HOME");
-            let mut path = PathBuf::from(home);
-            path.push(".config/rdm/rdm.lock");
-            std::fs::canonicalize(path)?
-        }
+        None => config::config_dir(&config_dir)?.join("rdm.lock"),
     };
 
-    let conf = TomlConfig::new(&repo_path, &worktree_path.to_str())?;
+    let conf = TomlConfig::new(
+        &repo_path_buf.to_str().map(String::from),
+        &worktree_path.to_str(),
+    )?;
     conf.save(&lock_path)?;
 
     let abs_worktree = std::fs::canonicalize(&worktree_path)?;
@@ -175,7 +170,7 @@ pub(crate) fn run(
     index.write()?;
 
     let oid = repo.commit(
-        Some("HEAD"),
+        None,
         &repo.signature()?,
         &repo.signature()?,
         "Initial commit",
@@ -190,7 +185,12 @@ pub(crate) fn run(
     let host_branch = repo.branch(&host, &commit, true)?;
 
     repo.set_head(host_branch.get().name().unwrap())?;
-    repo.checkout_head(Some(CheckoutBuilder::default().force()))?;
+    backup::safe_checkout_head(
+        &repo,
+        lock_path.parent().unwrap(),
+        "init",
+        &mut CheckoutBuilder::default(),
+    )?;
 
     log::info!("Initial commit created.");
 
@@ -0,0 +1,487 @@
+use std::{collections::BTreeMap, os::unix::fs::PermissionsExt, path::Path};
+
+use git2::{
+    build::CheckoutBuilder, Index, IndexEntry, IndexTime, ObjectType, Oid,
+    Repository, Status, StatusOptions, StatusShow, Tree,
+};
+
+use crate::backup;
+use crate::utils;
+
+/// Branch holding the files shared by every host, unless `rdm.sharedBranch` is
+/// set in the repository config.
+pub(crate) const DEFAULT_SHARED_BRANCH: &str = "main";
+
+/// Prefix of the branches holding the files of a group of hosts.
+pub(crate) const GROUP_PREFIX: &str = "group/";
+
+/// Repository config key listing the groups of the current host, as declared
+/// by the `groups` Lua function.
+pub(crate) const GROUPS_KEY: &str = "rdm.groups";
+
+pub(crate) fn shared_branch(repo: &Repository) -> Result<String, git2::Error> {
+    match repo.config()?.snapshot()?.get_string("rdm.sharedBranch") {
+        Ok(name) => Ok(name),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => {
+            Ok(DEFAULT_SHARED_BRANCH.to_string())
+        }
+        Err(err) => Err(err),
+    }
+}
+
+pub(crate) fn shared_ref(repo: &Repository) -> Result<String, git2::Error> {
+    Ok(format!("refs/heads/{}", shared_branch(repo)?))
+}
+
+pub(crate) fn group_ref(group: &str) -> String {
+    format!("refs/heads/{}{}", GROUP_PREFIX, group)
+}
+
+/// Groups of the current host, by order of priority.
+pub(crate) fn groups(repo: &Repository) -> Result<Vec<String>, git2::Error> {
+    match repo.config()?.snapshot()?.get_string(GROUPS_KEY) {
+        Ok(groups) => Ok(groups
+            .split(',')
+            .filter(|g| !g.is_empty())
+            .map(String::from)
+            .collect()),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(Vec::new()),
+        Err(err) => Err(err),
+    }
+}
+
+pub(crate) fn set_groups(
+    repo: &Repository,
+    groups: &[String],
+) -> Result<(), git2::Error> {
+    let mut config = repo.config()?;
+
+    if groups.is_empty() {
+        match config.remove(GROUPS_KEY) {
+            Err(err) if err.code() != git2::ErrorCode::NotFound => Err(err),
+            _ => Ok(()),
+        }
+    } else {
+        config.set_str(GROUPS_KEY, &groups.join(","))
+    }
+}
+
+/// References of the layers below the host, from the lowest to the highest
+/// priority: the shared layer then the groups of the host.
+pub(crate) fn layer_refs(
+    repo: &Repository,
+) -> Result<Vec<String>, git2::Error> {
+    let mut refs = vec![shared_ref(repo)?];
+    refs.extend(groups(repo)?.iter().rev().map(|g| group_ref(g)));
+    Ok(refs)
+}
+
+pub(crate) fn layer_commit<'r>(
+    repo: &'r Repository,
+    refname: &str,
+) -> Result<Option<git2::Commit<'r>>, git2::Error> {
+    match repo.find_reference(refname) {
+        Ok(reference) => Ok(Some(reference.peel_to_commit()?)),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+pub(crate) fn layer_tree<'r>(
+    repo: &'r Repository,
+    refname: &str,
+) -> Result<Option<Tree<'r>>, git2::Error> {
+    layer_commit(repo, refname)?
+        .map(|commit| commit.tree())
+        .transpose()
+}
+
+/// Existing layers below the host with their reference, from the lowest to
+/// the highest priority.
+fn layer_trees(
+    repo: &Repository,
+) -> Result<Vec<(String, Tree<'_>)>, git2::Error> {
+    let mut trees = Vec::new();
+
+    for refname in layer_refs(repo)? {
+        if let Some(tree) = layer_tree(repo, &refname)? {
+            trees.push((refname, tree));
+        }
+    }
+
+    Ok(trees)
+}
+
+/// Lists the blobs of `tree` as `(path, id, filemode)`.
+pub(crate) fn blobs(
+    tree: &Tree,
+) -> Result<Vec<(String, Oid, u32)>, git2::Error> {
+    let mut blobs = Vec::new();
+
+    tree.walk(git2::TreeWalkMode::PreOrder, |root, entry| {
+        if entry.kind() == Some(ObjectType::Blob) {
+            blobs.push((
+                format!("{}{}", root, entry.name().unwrap()),
+                entry.id(),
+                entry.filemode() as u32,
+            ));
+        }
+        git2::TreeWalkResult::Ok
+    })?;
+
+    Ok(blobs)
+}
+
+pub(crate) fn index_entry(path: &str, id: Oid, mode: u32) -> IndexEntry {
+    IndexEntry {
+        ctime: IndexTime::new(0, 0),
+        mtime: IndexTime::new(0, 0),
+        dev: 0,
+        ino: 0,
+        mode,
+        uid: 0,
+        gid: 0,
+        file_size: 0,
+        id,
+        flags: 0,
+        flags_extended: 0,
+        path: path.as_bytes().to_vec(),
+    }
+}
+
+/// Builds the tree made of `base` with the files of `top` on top of it.
+pub(crate) fn overlay<'r>(
+    repo: &'r Repository,
+    base: &Tree,
+    top: &Tree,
+) -> Result<Tree<'r>, git2::Error> {
+    let mut index = Index::new()?;
+    index.read_tree(base)?;
+
+    for (path, id, mode) in blobs(top)? {
+        index.add(&index_entry(&path, id, mode))?;
+    }
+
+    repo.find_tree(index.write_tree_to(repo)?)
+}
+
+/// Combines the layers with the tree of a host, the host files win.
+pub(crate) fn combined_tree<'r>(
+    repo: &'r Repository,
+    host_tree: &Tree<'r>,
+) -> Result<Tree<'r>, git2::Error> {
+    let mut combined: Option<Tree> = None;
+
+    for (_, tree) in layer_trees(repo)? {
+        combined = Some(match combined {
+            Some(base) => overlay(repo, &base, &tree)?,
+            None => tree,
+        });
+    }
+
+    match combined {
+        Some(base) => overlay(repo, &base, host_tree),
+        None => Ok(host_tree.clone()),
+    }
+}
+
+/// The combined tree of the current host, or `None` if HEAD is unborn.
+pub(crate) fn head_tree(
+    repo: &Repository,
+) -> Result<Option<Tree<'_>>, git2::Error> {
+    match repo.head() {
+        Ok(head) => Ok(Some(combined_tree(repo, &head.peel_to_tree()?)?)),
+        Err(err) if err.code() == git2::ErrorCode::UnbornBranch => Ok(None),
+        Err(err) => Err(err),
+    }
+}
+
+/// Backs up and force-checks out the combined tree of `host_tree`.
+pub(crate) fn checkout<'r>(
+    repo: &'r Repository,
+    config_path: &Path,
+    host_tree: &Tree<'r>,
+    reason: &str,
+    builder: &mut CheckoutBuilder,
+) -> Result<Option<String>, backup::BackupError> {
+    let tree = combined_tree(repo, host_tree)?;
+    backup::safe_checkout_tree(repo, config_path, &tree, reason, builder)
+}
+
+/// Same as [`checkout`] for the tree of HEAD.
+pub(crate) fn checkout_head(
+    repo: &Repository,
+    config_path: &Path,
+    reason: &str,
+    builder: &mut CheckoutBuilder,
+) -> Result<Option<String>, backup::BackupError> {
+    let host_tree = repo.head()?.peel_to_tree()?;
+    checkout(repo, config_path, &host_tree, reason, builder)
+}
+
+/// Moves the worktree and the index from the combined tree `old` to `new`,
+/// only touching the files that differ between them. The files with local
+/// changes are backed up before being replaced.
+pub(crate) fn update_worktree(
+    repo: &Repository,
+    config_path: &Path,
+    old: &Tree,
+    new: &Tree,
+    reason: &str,
+) -> Result<(), backup::BackupError> {
+    let worktree = repo.workdir().ok_or(backup::BackupError::NoWorktree)?;
+    let diff = repo.diff_tree_to_tree(Some(old), Some(new), None)?;
+
+    let mut modified = Vec::new();
+    let mut changes = Vec::new();
+
+    for delta in diff.deltas() {
+        let path = match delta.new_file().path().or(delta.old_file().path()) {
+            Some(path) => path.to_path_buf(),
+            None => continue,
+        };
+        let abs_path = worktree.join(&path);
+        if abs_path.is_file()
+            && Oid::hash_file(ObjectType::Blob, &abs_path)?
+                != delta.old_file().id()
+        {
+            modified.push(path.clone());
+        }
+
+        let new_file = delta.new_file();
+        let content = if new_file.exists() {
+            Some((new_file.id(), u32::from(new_file.mode())))
+        } else {
+            None
+        };
+        changes.push((path, content));
+    }
+
+    backup::create(repo, config_path, &modified, reason)?;
+
+    let mut index = repo.index()?;
+    for (path, content) in changes {
+        let abs_path = worktree.join(&path);
+        match content {
+            Some((id, mode)) => {
+                if let Some(parent) = abs_path.parent() {
+                    std::fs::create_dir_all(parent)?;
+                }
+                std::fs::write(&abs_path, repo.find_blob(id)?.content())?;
+                std::fs::set_permissions(
+                    &abs_path,
+                    std::fs::Permissions::from_mode(mode & 0o777),
+                )?;
+                index.add_path(&path)?;
+            }
+            None => {
+                if abs_path.is_file() {
+                    std::fs::remove_file(&abs_path)?;
+                }
+                index.remove_path(&path)?;
+            }
+        }
+    }
+    index.write()?;
+
+    Ok(())
+}
+
+/// Status of the tracked files against the combined tree of the current host,
+/// sorted by path.
+pub(crate) fn statuses(
+    repo: &Repository,
+    include_untracked: bool,
+) -> Result<Vec<(String, Status)>, git2::Error> {
+    let mut statuses: BTreeMap<String, Status> = BTreeMap::new();
+
+    let head = head_tree(repo)?;
+    let index = repo.index()?;
+    let diff = repo.diff_tree_to_index(head.as_ref(), Some(&index), None)?;
+
+    for delta in diff.deltas() {
+        let status = match delta.status() {
+            git2::Delta::Added => Status::INDEX_NEW,
+            git2::Delta::Deleted => Status::INDEX_DELETED,
+            git2::Delta::Modified => Status::INDEX_MODIFIED,
+            git2::Delta::Renamed => Status::INDEX_RENAMED,
+            git2::Delta::Typechange => Status::INDEX_TYPECHANGE,
+            _ => continue,
+        };
+        let file = match delta.status() {
+            git2::Delta::Deleted => delta.old_file(),
+            _ => delta.new_file(),
+        };
+        if let Some(path) = file.path().and_then(|p| p.to_str()) {
+            *statuses.entry(path.to_string()).or_insert(Status::CURRENT) |=
+                status;
+        }
+    }
+
+    let mut status_opts = StatusOptions::new();
+    status_opts.show(StatusShow::Workdir);
+    status_opts.include_unmodified(false);
+    status_opts.include_untracked(include_untracked);
+    status_opts.include_ignored(false);
+
+    for entry in repo.statuses(Some(&mut status_opts))?.iter() {
+        if let Some(path) = entry.path() {
+            *statuses.entry(path.to_string()).or_insert(Status::CURRENT) |=
+                entry.status();
+        }
+    }
+
+    Ok(statuses
+        .into_iter()
+        .filter(|(_, status)| *status != Status::CURRENT)
+        .collect())
+}
+
+fn overrides_path(repo: &Repository) -> std::path::PathBuf {
+    repo.path().join("rdm-overrides")
+}
+
+/// Paths of the layers that the next save will copy to the host.
+pub(crate) fn pending_overrides(
+    repo: &Repository,
+) -> Result<Vec<String>, std::io::Error> {
+    let path = overrides_path(repo);
+
+    if !path.exists() {
+        return Ok(Vec::new());
+    }
+
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .map(String::from)
+        .collect())
+}
+
+/// Marks `path`, a file of a layer, to be overridden by the host on the next
+/// save.
+pub(crate) fn add_override(
+    repo: &Repository,
+    path: &str,
+) -> Result<(), std::io::Error> {
+    let mut overrides = pending_overrides(repo)?;
+
+    if !overrides.iter().any(|p| p == path) {
+        overrides.push(path.to_string());
+        utils::write_atomically(
+            &overrides_path(repo),
+            overrides.join("\n").as_bytes(),
+        )?;
+    }
+
+    Ok(())
+}
+
+pub(crate) fn clear_overrides(repo: &Repository) -> Result<(), std::io::Error> {
+    let path = overrides_path(repo);
+
+    if path.exists() {
+        std::fs::remove_file(path)?;
+    }
+
+    Ok(())
+}
+
+/// Returns the reference of the layer `path` comes from, if the current host
+/// does not override it.
+pub(crate) fn layer_of(
+    repo: &Repository,
+    path: &Path,
+) -> Result<Option<String>, git2::Error> {
+    let host = repo.head()?.peel_to_tree()?;
+
+    if host.get_path(path).is_ok() {
+        return Ok(None);
+    }
+
+    Ok(layer_trees(repo)?
+        .into_iter()
+        .rev()
+        .find(|(_, tree)| tree.get_path(path).is_ok())
+        .map(|(refname, _)| refname))
+}
+
+/// Splits `tree`, built from the index, into the new tree of the host and the
+/// new trees of the layers. Files coming from a layer that the host does not
+/// override, and are not in `overrides`, are saved to the layer with the
+/// highest priority that has them, everything else belongs to the host. Only
+/// the layers that changed are returned, with their reference.
+pub(crate) fn split<'r>(
+    repo: &'r Repository,
+    tree: &Tree<'r>,
+    overrides: &[String],
+) -> Result<(Tree<'r>, Vec<(String, Tree<'r>)>), git2::Error> {
+    let layers = layer_trees(repo)?;
+    let host = repo.head()?.peel_to_tree()?;
+
+    // Index of the layer owning `path`, if any.
+    let owner = |path: &str| {
+        if overrides.iter().any(|p| p == path) {
+            return None;
+        }
+        let path = Path::new(path);
+        if host.get_path(path).is_ok() {
+            return None;
+        }
+        layers
+            .iter()
+            .rposition(|(_, tree)| tree.get_path(path).is_ok())
+    };
+
+    let mut host_index = Index::new()?;
+    let mut layer_indexes = Vec::new();
+    for (_, layer_tree) in layers.iter() {
+        let mut index = Index::new()?;
+        index.read_tree(layer_tree)?;
+        layer_indexes.push(index);
+    }
+
+    for (path, id, mode) in blobs(tree)? {
+        match owner(&path) {
+            Some(i) => layer_indexes[i].add(&index_entry(&path, id, mode))?,
+            None => host_index.add(&index_entry(&path, id, mode))?,
+        }
+    }
+
+    for (i, (_, layer_tree)) in layers.iter().enumerate() {
+        for (path, _, _) in blobs(layer_tree)? {
+            if owner(&path) == Some(i)
+                && tree.get_path(Path::new(&path)).is_err()
+            {
+                layer_indexes[i].remove_path(Path::new(&path))?;
+            }
+        }
+    }
+
+    let host_tree = repo.find_tree(host_index.write_tree_to(repo)?)?;
+
+    let mut changed = Vec::new();
+    for ((refname, layer_tree), mut index) in
+        layers.into_iter().zip(layer_indexes)
+    {
+        let oid = index.write_tree_to(repo)?;
+        if oid != layer_tree.id() {
+            changed.push((refname, repo.find_tree(oid)?));
+        }
+    }
+
+    Ok((host_tree, changed))
+}
+
+/// Commits `tree` on the layer `refname`, creating it if needed.
+pub(crate) fn commit_layer(
+    repo: &Repository,
+    refname: &str,
+    tree: &Tree,
+    msg: &str,
+) -> Result<Oid, git2::Error> {
+    let sig = repo.signature()?;
+    let parent = layer_commit(repo, refname)?;
+    let parents: Vec<&git2::Commit> = parent.iter().collect();
+
+    repo.commit(Some(refname), &sig, &sig, msg, tree, &parents)
+}
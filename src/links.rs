@@ -0,0 +1,238 @@
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+};
+
+use git2::Repository;
+use rdm_lua::{LinkEntry, Manifest};
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{backup, config::Config, utils};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "Error while linking your files:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum LinkError {
+    #[doc_to_string]
+    IoError(std::io::Error),
+    BackupError(backup::BackupError),
+}
+
+/// How the target of a link declared in `init.lua` differs from it.
+#[derive(Debug, PartialEq, Eq)]
+pub(crate) enum LinkState {
+    UpToDate,
+    /// Nothing is at the target.
+    Missing,
+    /// The target links to its source, which does not exist.
+    Broken,
+    /// Another file or link is at the target.
+    Foreign,
+}
+
+use LinkState::*;
+
+fn links_path(repo: &Repository) -> PathBuf {
+    repo.path().join("rdm-links")
+}
+
+/// The links created by rdm, their source by target.
+fn created_links(
+    repo: &Repository,
+) -> Result<BTreeMap<PathBuf, PathBuf>, LinkError> {
+    let path = links_path(repo);
+
+    if !path.exists() {
+        return Ok(BTreeMap::new());
+    }
+
+    Ok(std::fs::read_to_string(path)?
+        .lines()
+        .filter_map(|line| line.split_once('\t'))
+        .map(|(target, source)| (PathBuf::from(target), PathBuf::from(source)))
+        .collect())
+}
+
+fn set_created_links(
+    repo: &Repository,
+    links: &BTreeMap<PathBuf, PathBuf>,
+) -> Result<(), LinkError> {
+    let lines: Vec<String> = links
+        .iter()
+        .map(|(target, source)| {
+            format!(
+                "{}\t{}",
+                target.to_str().unwrap(),
+                source.to_str().unwrap()
+            )
+        })
+        .collect();
+    utils::write_atomically(&links_path(repo), lines.join("\n").as_bytes())?;
+
+    Ok(())
+}
+
+/// Whether `abs_target` is a link to `abs_source`.
+fn links_to(abs_target: &Path, abs_source: &Path) -> bool {
+    std::fs::read_link(abs_target).is_ok_and(|dest| dest == abs_source)
+}
+
+pub(crate) fn state(
+    config: &Config,
+    link: &LinkEntry,
+) -> Result<LinkState, LinkError> {
+    let abs_source = config.worktree_path.join(&link.source);
+    let abs_target = config.worktree_path.join(&link.target);
+
+    match std::fs::symlink_metadata(&abs_target) {
+        Err(err) if err.kind() == std::io::ErrorKind::NotFound => Ok(Missing),
+        Err(err) => Err(err.into()),
+        Ok(_) if !links_to(&abs_target, &abs_source) => Ok(Foreign),
+        Ok(_) if !abs_source.exists() => Ok(Broken),
+        Ok(_) => Ok(UpToDate),
+    }
+}
+
+/// Moves what is at the target of `link` out of the way, a regular file is
+/// backed up first. Returns `false` if it can not be replaced.
+fn replace(config: &Config, link: &LinkEntry) -> Result<bool, LinkError> {
+    let abs_target = config.worktree_path.join(&link.target);
+    let file_type = std::fs::symlink_metadata(&abs_target)?.file_type();
+
+    if file_type.is_dir() {
+        log::warn!(
+            "The directory {} is in the way of a link, it is not replaced.",
+            link.target.to_str().unwrap()
+        );
+        return Ok(false);
+    }
+
+    if config.dry_run {
+        log::info!(
+            "Would replace {} by a link to {}.",
+            link.target.to_str().unwrap(),
+            link.source.to_str().unwrap()
+        );
+        return Ok(false);
+    }
+
+    if file_type.is_file() {
+        backup::create(
+            &config.repo,
+            &config.config_path,
+            std::slice::from_ref(&link.target),
+            "linking",
+        )?;
+    }
+    std::fs::remove_file(&abs_target)?;
+
+    Ok(true)
+}
+
+/// Creates the link declared by `link` if it is missing. What is already at
+/// its target is only replaced with `force`.
+pub(crate) fn deploy(
+    config: &Config,
+    link: &LinkEntry,
+) -> Result<(), LinkError> {
+    let abs_source = config.worktree_path.join(&link.source);
+    let abs_target = config.worktree_path.join(&link.target);
+
+    match state(config, link)? {
+        UpToDate => return Ok(()),
+        Broken => {
+            log::warn!(
+                "The link {} is broken, {} does not exist.",
+                link.target.to_str().unwrap(),
+                link.source.to_str().unwrap()
+            );
+            return Ok(());
+        }
+        Foreign if !link.force => {
+            log::warn!(
+                "{} is not a link to {}, set `force = true' to replace it.",
+                link.target.to_str().unwrap(),
+                link.source.to_str().unwrap()
+            );
+            return Ok(());
+        }
+        Foreign => {
+            if !replace(config, link)? {
+                return Ok(());
+            }
+        }
+        Missing if config.dry_run => {
+            log::info!(
+                "Would link {} to {}.",
+                link.target.to_str().unwrap(),
+                link.source.to_str().unwrap()
+            );
+            return Ok(());
+        }
+        Missing => (),
+    }
+
+    if let Some(parent) = abs_target.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::os::unix::fs::symlink(&abs_source, &abs_target)?;
+
+    let mut links = created_links(&config.repo)?;
+    links.insert(link.target.clone(), link.source.clone());
+    set_created_links(&config.repo, &links)?;
+
+    log::info!(
+        "Linked {} to {}.",
+        link.target.to_str().unwrap(),
+        link.source.to_str().unwrap()
+    );
+
+    Ok(())
+}
+
+/// Removes the links created by rdm that `manifest` does not declare anymore,
+/// unless they were changed since.
+pub(crate) fn remove_undeclared(
+    config: &Config,
+    manifest: &Manifest,
+) -> Result<(), LinkError> {
+    let mut links = created_links(&config.repo)?;
+    let undeclared: Vec<(PathBuf, PathBuf)> = links
+        .iter()
+        .filter(|(target, _)| {
+            !manifest.links.iter().any(|link| &link.target == *target)
+        })
+        .map(|(target, source)| (target.clone(), source.clone()))
+        .collect();
+
+    if undeclared.is_empty() {
+        return Ok(());
+    }
+
+    for (target, source) in undeclared {
+        let abs_target = config.worktree_path.join(&target);
+        let ours = links_to(&abs_target, &config.worktree_path.join(&source));
+
+        if config.dry_run {
+            if ours {
+                log::info!(
+                    "Would remove the link {}.",
+                    target.to_str().unwrap()
+                );
+            }
+            continue;
+        }
+
+        if ours {
+            std::fs::remove_file(&abs_target)?;
+            log::info!("Removed the link {}.", target.to_str().unwrap());
+        }
+        links.remove(&target);
+    }
+
+    if !config.dry_run {
+        set_created_links(&config.repo, &links)?;
+    }
+
+    Ok(())
+}
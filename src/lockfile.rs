@@ -3,7 +3,7 @@ use std::{fs::File, io::Write, path::PathBuf};
 use rdm_macros::{FromError, ToDoc};
 use serde::{Deserialize, Serialize};
 
-use crate::config::Config;
+use crate::utils;
 
 #[derive(Debug, FromError, ToDoc)]
 #[doc_prefix = "Error while using `rdm.lock'"]
@@ -18,13 +18,54 @@ pub(crate) enum LockFileError {
     TomlSerError(toml::ser::Error),
     #[doc_to_string]
     EnvError(std::env::VarError),
+    #[doc_to_string]
+    ExpandError(utils::ExpandError),
+    #[doc_format(
+        format_str = "The lockfile uses the format version {}, this version \
+                      of rdm only supports up to {}, please upgrade rdm.",
+        _1,
+        _2
+    )]
+    #[no_from]
+    TooNewError(u32, u32),
+    #[doc_text = "The `format_version' of the lockfile is not a valid number."]
+    InvalidVersionError,
 }
 
+/// Upgrades a lockfile from one format version to the next one.
+type Migration = fn(&mut toml::value::Table);
+
+/// The migrations to apply to a lockfile, the one at index `n` upgrades the
+/// format version `n` to `n + 1`.
+const MIGRATIONS: [Migration; 3] = [
+    // The version 0 only lacks the `format_version' key.
+    |_| {},
+    // The version 1 stores absolute paths.
+    |table| {
+        for key in ["repo_path", "worktree_path"] {
+            if let Some(toml::Value::String(path)) = table.get_mut(key) {
+                *path = utils::portable_path(path);
+            }
+        }
+    },
+    // The version 2 stores the revision, it now comes from the history.
+    |table| {
+        table.remove("revision");
+    },
+];
+
+/// The format version of the lockfiles written by this version of rdm.
+pub(crate) const FORMAT_VERSION: u32 = MIGRATIONS.len() as u32;
+
+/// The content of `rdm.lock`. As the lockfile is saved in every host branch,
+/// the paths are stored with `~` or a XDG variable in place of the directories
+/// that differ between machines, use `repo_path()` and `worktree_path()` to
+/// get them expanded.
 #[derive(Serialize, Deserialize, Debug)]
 pub(crate) struct TomlConfig {
+    pub(crate) format_version: u32,
     pub(crate) repo_path: String,
     pub(crate) worktree_path: String,
-    pub(crate) revision: u32,
 }
 
 impl TomlConfig {
@@ -52,70 +93,173 @@ impl TomlConfig {
         };
 
         Ok(Self {
-            repo_path,
-            worktree_path,
-            revision: 1,
+            format_version: FORMAT_VERSION,
+            repo_path: utils::portable_path(&repo_path),
+            worktree_path: utils::portable_path(&worktree_path),
         })
     }
 
-    pub(crate) fn save(
-        self: &TomlConfig,
-        path: &PathBuf,
-    ) -> Result<(), LockFileError> {
+    pub(crate) fn repo_path(&self) -> Result<PathBuf, LockFileError> {
+        Ok(utils::full_expand(&self.repo_path)?.into())
+    }
+
+    pub(crate) fn worktree_path(&self) -> Result<PathBuf, LockFileError> {
+        Ok(utils::full_expand(&self.worktree_path)?.into())
+    }
+
+    /// The content of the lockfile, as written by `save`.
+    pub(crate) fn contents(self: &TomlConfig) -> Result<String, LockFileError> {
         let str = r#"# This file is automatically @generated by rdm.
 # It is not intended for manual editing."#
             .to_string();
 
         let toml = toml::to_string(self)?;
 
-        let str = format!("{}\n{}", str, toml);
-        if !path.exists() {
-            let mut lock_file = File::create(path)?;
-            lock_file.flush()?;
-        }
+        Ok(format!("{}\n{}", str, toml))
+    }
 
-        let mut lock_file = std::fs::OpenOptions::new()
-            .write(true)
-            .truncate(true)
-            .open(path)?;
+    /// Writes the lockfile to `path`. The content goes to a temporary file
+    /// first, which then replaces the lockfile, so that it is never left half
+    /// written.
+    pub(crate) fn save(
+        self: &TomlConfig,
+        path: &PathBuf,
+    ) -> Result<(), LockFileError> {
+        let str = self.contents()?;
+        let tmp_path = path.with_extension("lock.tmp");
+
+        let mut lock_file = File::create(&tmp_path)?;
         lock_file.write_all(str.as_bytes())?;
         lock_file.flush()?;
+        lock_file.sync_all()?;
+
+        std::fs::rename(&tmp_path, path)?;
 
         Ok(())
     }
 
-    pub(crate) fn load(path: &PathBuf) -> Result<TomlConfig, LockFileError> {
+    /// Parses the content of a lockfile, upgrading it to `FORMAT_VERSION`.
+    /// Returns the lockfile and the format version it was written with.
+    pub(crate) fn parse(str: &str) -> Result<(TomlConfig, u32), LockFileError> {
+        let mut table: toml::value::Table = toml::from_str(str)?;
+
+        let version = match table.get("format_version") {
+            None => 0,
+            Some(toml::Value::Integer(version)) => u32::try_from(*version)
+                .map_err(|_| LockFileError::InvalidVersionError)?,
+            Some(_) => return Err(LockFileError::InvalidVersionError),
+        };
+
+        if version > FORMAT_VERSION {
+            return Err(LockFileError::TooNewError(version, FORMAT_VERSION));
+        }
+
+        for migration in MIGRATIONS.iter().skip(version as usize) {
+            migration(&mut table);
+        }
+        table.insert(
+            "format_version".to_string(),
+            toml::Value::Integer(FORMAT_VERSION.into()),
+        );
+
+        Ok((toml::Value::Table(table).try_into()?, version))
+    }
+
+    /// Loads the lockfile at `path`. A lockfile written with an older format
+    /// version is upgraded in place, the original is kept next to it, unless
+    /// `dry_run` is set.
+    pub(crate) fn load(
+        path: &PathBuf,
+        dry_run: bool,
+    ) -> Result<TomlConfig, LockFileError> {
         let str = std::fs::read_to_string(path)?;
 
-        Ok(toml::from_str(str.as_str())?)
+        let (conf, version) = Self::parse(&str)?;
+
+        if version < FORMAT_VERSION && dry_run {
+            log::info!(
+                "Would upgrade {} to the format version {}.",
+                path.to_string_lossy(),
+                FORMAT_VERSION
+            );
+        } else if version < FORMAT_VERSION {
+            let backup_path = path.with_extension(format!("lock.v{}", version));
+            std::fs::copy(path, &backup_path)?;
+            conf.save(path)?;
+            log::info!(
+                "Upgraded {} to the format version {}, the original was saved \
+                 to {}.",
+                path.to_string_lossy(),
+                FORMAT_VERSION,
+                backup_path.to_string_lossy()
+            );
+        }
+
+        Ok(conf)
     }
 }
 
-pub(crate) fn increment_revision(config: &Config) -> Result<(), LockFileError> {
-    let mut lockfile_path = config.config_path.clone();
-    lockfile_path.push("rdm.lock");
-
-    let lockfile = std::fs::read_to_string(&lockfile_path)?;
+#[cfg(test)]
+mod tests {
+    use super::*;
 
-    let conf = toml::de::from_str::<TomlConfig>(lockfile.as_str())?;
+    #[test]
+    fn migrations_upgrade_baseline_lockfile() {
+        let home = std::fs::canonicalize(std::env::var("HOME").unwrap())
+            .unwrap()
+            .to_string_lossy()
+            .to_string();
+        let str = format!(
+            "repo_path = \"/nonexistent-rdm/repo\"\nworktree_path = \"{}\"\n\
+             revision = 3\n",
+            home
+        );
 
-    let new_config = TomlConfig {
-        revision: conf.revision + 1,
-        ..conf
-    };
+        let mut table: toml::value::Table = toml::from_str(&str).unwrap();
+        for migration in MIGRATIONS.iter() {
+            migration(&mut table);
+        }
+        assert!(!table.contains_key("revision"));
+        assert_eq!(table["worktree_path"].as_str(), Some("~"));
 
-    new_config.save(&lockfile_path)?;
+        let (conf, version) = TomlConfig::parse(&str).unwrap();
+        assert_eq!(version, 0);
+        assert_eq!(conf.format_version, FORMAT_VERSION);
+        assert_eq!(conf.repo_path, "/nonexistent-rdm/repo");
+        assert_eq!(conf.worktree_path, "~");
+        assert!(!conf.contents().unwrap().contains("revision"));
+    }
 
-    let repo = &config.repo;
-    let mut index = repo.index()?;
+    #[test]
+    fn parse_refuses_newer_format_version() {
+        let str = format!(
+            "format_version = {}\nrepo_path = \"~/.rdm/repo\"\n\
+             worktree_path = \"~\"\n",
+            FORMAT_VERSION + 1
+        );
 
-    let worktree_abs = std::fs::canonicalize(&config.worktree_path)?;
-    let lockfile_abs = std::fs::canonicalize(&lockfile_path)?;
+        match TomlConfig::parse(&str) {
+            Err(LockFileError::TooNewError(version, supported)) => {
+                assert_eq!(version, FORMAT_VERSION + 1);
+                assert_eq!(supported, FORMAT_VERSION);
+            }
+            res => panic!("unexpected result: {:?}", res),
+        }
+    }
 
-    index.add_path(
-        &pathdiff::diff_paths(&lockfile_abs, &worktree_abs).unwrap(),
-    )?;
-    index.write()?;
+    #[test]
+    fn parse_refuses_invalid_format_version() {
+        for version in ["\"3\"", "-1", "1.5"] {
+            let str = format!(
+                "format_version = {}\nrepo_path = \"~/.rdm/repo\"\n\
+                 worktree_path = \"~\"\n",
+                version
+            );
 
-    Ok(())
+            assert!(matches!(
+                TomlConfig::parse(&str),
+                Err(LockFileError::InvalidVersionError)
+            ));
+        }
+    }
 }
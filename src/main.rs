@@ -1,22 +1,55 @@
 #![allow(clippy::enum_variant_names)]
+mod apply_command;
 mod args;
+mod backup;
+mod backup_command;
 mod bootstrap_command;
+mod cache_command;
+mod clone_command;
 mod config;
 mod config_command;
+mod copies;
+mod deploy;
+mod doctor_command;
+mod host_command;
+mod hosts;
 mod init_command;
+mod layers;
+mod links;
 mod lockfile;
 mod rdm_error;
+mod revision;
 mod run_command;
+mod secret_command;
+mod secrets;
 mod utils;
 
 use std::process::exit;
 
 use clap::Parser;
 
-use args::{Args, Commands};
+use args::{
+    Args, BackupSubCommand, Commands, ConfigSubCommand, HostSubCommand,
+};
 use config::Config;
 use rdm_error::RdmError;
 
+/// Whether `command` can run with `--dry-run`, either because it supports it
+/// or because it does not change anything.
+fn supports_dry_run(command: &Commands) -> bool {
+    match command {
+        Commands::Config(ConfigSubCommand::Propagate { .. }) => false,
+        Commands::Config(_) | Commands::Apply | Commands::Doctor => true,
+        Commands::Host(HostSubCommand::List | HostSubCommand::Diff { .. }) => {
+            true
+        }
+        Commands::Backup(BackupSubCommand::List)
+        | Commands::Secret(_)
+        | Commands::Cache(_) => true,
+        _ => false,
+    }
+}
+
 //IDEAS:
 //  * Manage hosts by branches
 //  * Enable copy paste in Lua
@@ -25,13 +58,36 @@ fn main() {
 
     let args = Args::parse();
 
-    let res = if let Commands::Init {
+    let res = if args.dry_run && !supports_dry_run(&args.command) {
+        Err(RdmError::DryRunUnsupported)
+    } else if let Commands::Init {
         repo,
         config,
         worktree,
     } = args.command
     {
-        init_command::run(repo, config, worktree).map_err(RdmError::from)
+        init_command::run(repo, config, worktree, args.config_path)
+            .map_err(RdmError::from)
+    } else if let Commands::Clone {
+        url,
+        repo,
+        worktree,
+        from,
+    } = args.command
+    {
+        clone_command::run(
+            url,
+            repo,
+            worktree,
+            from,
+            args.config_path,
+            args.offline,
+        )
+        .map_err(RdmError::from)
+    } else if let Commands::Doctor = args.command {
+        doctor_command::run(args.config_path).map_err(RdmError::from)
+    } else if let Commands::Cache(sub_command) = args.command {
+        cache_command::run(sub_command, args.dry_run).map_err(RdmError::from)
     } else {
         match Config::new(&args) {
             Err(err) => Err(err.into()),
@@ -40,9 +96,24 @@ fn main() {
                     config_command::run(sub_command, config)
                         .map_err(RdmError::from)
                 }
+                Commands::Apply => {
+                    apply_command::run(config).map_err(RdmError::from)
+                }
                 Commands::Bootstrap => {
                     bootstrap_command::run(config).map_err(RdmError::from)
                 }
+                Commands::Host(sub_command) => {
+                    host_command::run(sub_command, config)
+                        .map_err(RdmError::from)
+                }
+                Commands::Backup(sub_command) => {
+                    backup_command::run(sub_command, config)
+                        .map_err(RdmError::from)
+                }
+                Commands::Secret(sub_command) => {
+                    secret_command::run(sub_command, config)
+                        .map_err(RdmError::from)
+                }
                 _ => Ok(()),
             },
         }
@@ -1,7 +1,9 @@
 use rdm_macros::{FromError, ToDoc};
 
 use crate::{
-    bootstrap_command, config, config_command, init_command, run_command,
+    apply_command, backup_command, bootstrap_command, cache_command,
+    clone_command, config, config_command, doctor_command, host_command,
+    init_command, run_command, secret_command,
 };
 
 #[derive(Debug, FromError, ToDoc)]
@@ -12,5 +14,14 @@ pub(crate) enum RdmError {
     ConfigCommandError(config_command::ConfigCommandError),
     InitCommandError(init_command::InitError),
     BootstrapError(bootstrap_command::BootstrapError),
+    BackupCommandError(backup_command::BackupCommandError),
+    HostCommandError(host_command::HostCommandError),
+    CloneCommandError(clone_command::CloneError),
     RunCommandError(run_command::RunCommandError),
+    DoctorCommandError(doctor_command::DoctorError),
+    ApplyCommandError(apply_command::ApplyError),
+    SecretCommandError(secret_command::SecretCommandError),
+    CacheCommandError(cache_command::CacheCommandError),
+    #[doc_text = "This command does not support `--dry-run'."]
+    DryRunUnsupported,
 }
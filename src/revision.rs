@@ -0,0 +1,156 @@
+use git2::{Commit, Repository};
+
+/// Trailer of the commit messages holding the revision of the commit.
+pub(crate) const TRAILER: &str = "Rdm-Revision";
+
+/// Prefix of the summary of the revisions, the only place the revision was
+/// written before the trailer existed.
+const SUMMARY_PREFIX: &str = "Revision #";
+
+/// The revision written in the message of `commit`, if any.
+fn explicit_revision(commit: &Commit) -> Option<u32> {
+    let message = commit.message()?;
+    let trailer = format!("{}:", TRAILER);
+
+    message
+        .lines()
+        .rev()
+        .find_map(|line| line.strip_prefix(&trailer))
+        .and_then(|revision| revision.trim().parse().ok())
+        .or_else(|| {
+            let summary = commit.summary()?.strip_prefix(SUMMARY_PREFIX)?;
+            let end = summary
+                .find(|c: char| !c.is_ascii_digit())
+                .unwrap_or(summary.len());
+            summary[..end].parse().ok()
+        })
+}
+
+/// The revision of `commit`: the revision written in its message, or the one
+/// of its closest first parent that has one plus the number of commits between
+/// them, the first commit being the revision `0`. The revisions only depend on
+/// the history, so they never conflict.
+pub(crate) fn of_commit(commit: &Commit) -> Result<u32, git2::Error> {
+    let mut commit = commit.clone();
+    let mut distance = 0;
+
+    loop {
+        if let Some(revision) = explicit_revision(&commit) {
+            return Ok(revision + distance);
+        }
+        if commit.parent_count() == 0 {
+            return Ok(distance);
+        }
+        commit = commit.parent(0)?;
+        distance += 1;
+    }
+}
+
+/// The revision of HEAD, `0` if HEAD is unborn.
+pub(crate) fn current(repo: &Repository) -> Result<u32, git2::Error> {
+    match repo.head() {
+        Ok(head) => of_commit(&head.peel_to_commit()?),
+        Err(err) if err.code() == git2::ErrorCode::UnbornBranch => Ok(0),
+        Err(err) => Err(err),
+    }
+}
+
+/// The message of a commit saving the revision `revision`.
+pub(crate) fn message(revision: u32, details: Option<&str>) -> String {
+    let summary = match details {
+        Some(details) => format!("{}{}: {}", SUMMARY_PREFIX, revision, details),
+        None => format!("{}{}", SUMMARY_PREFIX, revision),
+    };
+
+    format!("{}\n\n{}: {}\n", summary, TRAILER, revision)
+}
+
+#[cfg(test)]
+mod tests {
+    use git2::{Oid, Signature};
+
+    use super::*;
+
+    /// An empty repository for the test `name`.
+    fn test_repo(name: &str) -> Repository {
+        let dir = std::env::temp_dir().join(format!(
+            "rdm-revision-{}-{}",
+            std::process::id(),
+            name
+        ));
+        let _ = std::fs::remove_dir_all(&dir);
+        Repository::init_bare(dir).unwrap()
+    }
+
+    /// Commits an empty tree with `message` on top of `parents`.
+    fn commit(repo: &Repository, message: &str, parents: &[Oid]) -> Oid {
+        let sig = Signature::now("rdm", "rdm@example.com").unwrap();
+        let tree = repo
+            .find_tree(repo.treebuilder(None).unwrap().write().unwrap())
+            .unwrap();
+        let parents: Vec<Commit> = parents
+            .iter()
+            .map(|id| repo.find_commit(*id).unwrap())
+            .collect();
+        let parents: Vec<&Commit> = parents.iter().collect();
+
+        repo.commit(None, &sig, &sig, message, &tree, &parents)
+            .unwrap()
+    }
+
+    fn revision(repo: &Repository, id: Oid) -> u32 {
+        of_commit(&repo.find_commit(id).unwrap()).unwrap()
+    }
+
+    #[test]
+    fn root_commit_is_revision_0() {
+        let repo = test_repo("root");
+        let root = commit(&repo, "Initial commit", &[]);
+
+        assert_eq!(revision(&repo, root), 0);
+    }
+
+    #[test]
+    fn revision_from_trailer() {
+        let repo = test_repo("trailer");
+        let root = commit(&repo, "Initial commit", &[]);
+        let id = commit(&repo, &message(7, Some("save")), &[root]);
+        // The trailer wins over a summary written by hand.
+        let edited = commit(
+            &repo,
+            &format!("Revision #2: edited\n\n{}: 12\n", TRAILER),
+            &[id],
+        );
+
+        assert_eq!(revision(&repo, id), 7);
+        assert_eq!(revision(&repo, edited), 12);
+    }
+
+    #[test]
+    fn revision_from_legacy_summary() {
+        let repo = test_repo("legacy");
+        let root = commit(&repo, "Initial commit", &[]);
+        let id = commit(&repo, "Revision #41", &[root]);
+        let detailed = commit(&repo, "Revision #42: pull", &[id]);
+
+        assert_eq!(revision(&repo, id), 41);
+        assert_eq!(revision(&repo, detailed), 42);
+    }
+
+    #[test]
+    fn revision_from_first_parent_distance() {
+        let repo = test_repo("distance");
+        let root = commit(&repo, "Initial commit", &[]);
+        let saved = commit(&repo, &message(5, None), &[root]);
+        let first = commit(&repo, "manual commit", &[saved]);
+        let second = commit(&repo, "another one", &[first]);
+        // Only the first parent counts, the other one has a higher revision.
+        let other = commit(&repo, &message(20, None), &[root]);
+        let merge = commit(&repo, "merge", &[second, other]);
+
+        assert_eq!(revision(&repo, first), 6);
+        assert_eq!(revision(&repo, second), 7);
+        assert_eq!(revision(&repo, merge), 8);
+        assert_eq!(revision(&repo, commit(&repo, "plain", &[root])), 1);
+    }
+}
@@ -1,6 +1,17 @@
 use rdm_macros::{FromError, ToDoc};
 
-use crate::config::Config;
+use std::path::{Path, PathBuf};
+
+use git2::Status;
+use rdm_lua::{FileEntry, Manifest};
+
+use crate::{
+    backup,
+    config::Config,
+    copies,
+    deploy::{self, DeployState},
+    hosts, layers, links, utils,
+};
 
 #[derive(Debug, FromError, ToDoc)]
 #[doc_prefix = "Error while running the run command:"]
@@ -12,9 +23,16 @@ pub(crate) enum RunCommandError {
     IoError(std::io::Error),
     #[doc_to_string]
     LuaError(mlua::Error),
+    RuntimeError(rdm_lua::RuntimeError),
+    #[doc_to_string]
+    GitError(git2::Error),
+    BackupError(backup::BackupError),
+    DeployError(deploy::DeployError),
+    LinkError(links::LinkError),
 }
 
-pub(crate) fn run(config: &Config) -> Result<(), RunCommandError> {
+/// Evaluates `init.lua` into its manifest, without changing anything.
+pub(crate) fn load(config: &Config) -> Result<Manifest, RunCommandError> {
     let mut lua_init_file = config.config_path.clone();
 
     lua_init_file.push("init.lua");
@@ -23,9 +41,262 @@ pub(crate) fn run(config: &Config) -> Result<(), RunCommandError> {
         return Err(RunCommandError::InitLuaNotFound);
     }
 
-    let str = std::fs::read_to_string(lua_init_file)?;
+    let str = std::fs::read_to_string(&lua_init_file)?;
+
+    Ok(
+        rdm_lua::evaluate(&config.lua, &str, lua_init_file.to_str().unwrap())
+            .map_err(rdm_lua::RuntimeError::from_lua)?,
+    )
+}
+
+/// Makes the groups declared by `manifest` the groups of this host, and
+/// updates the files of the worktree that come from their layers.
+pub(crate) fn apply_groups(
+    config: &Config,
+    manifest: &Manifest,
+) -> Result<(), RunCommandError> {
+    let repo = &config.repo;
+    let groups_before = layers::groups(repo)?;
+    let groups_after = manifest.groups.clone().unwrap_or_default();
+
+    if groups_before == groups_after {
+        return Ok(());
+    }
+
+    if config.dry_run {
+        log::info!(
+            "Would change the groups of this host from [{}] to [{}] and update \
+             the files.",
+            groups_before.join(", "),
+            groups_after.join(", ")
+        );
+        return Ok(());
+    }
+
+    let tree_before = layers::head_tree(repo)?;
+    layers::set_groups(repo, &groups_after)?;
+
+    if let (Some(old), Some(new)) = (tree_before, layers::head_tree(repo)?) {
+        log::info!("The groups of this host changed, updating the files.");
+        layers::update_worktree(
+            repo,
+            &config.config_path,
+            &old,
+            &new,
+            "changing groups",
+        )?;
+    }
+
+    Ok(())
+}
+
+/// The files of `manifest` that are part of the configuration of the current
+/// host.
+pub(crate) fn host_files<'m>(
+    config: &Config,
+    manifest: &'m Manifest,
+) -> Result<Vec<&'m FileEntry>, RunCommandError> {
+    let host = hosts::current_host(&config.repo)?;
+
+    Ok(manifest
+        .files
+        .iter()
+        .filter(|entry| match &host {
+            Some(host) => entry.applies_to(host),
+            None => true,
+        })
+        .collect())
+}
+
+/// The secret file of `manifest` deployed at `path`, given relative to the
+/// current directory, if any.
+pub(crate) fn secret_entry<'m>(
+    config: &Config,
+    manifest: &'m Manifest,
+    path: &Path,
+) -> Result<Option<&'m FileEntry>, RunCommandError> {
+    let path = utils::worktree_relative(path, &config.worktree_path)?;
+
+    Ok(host_files(config, manifest)?
+        .into_iter()
+        .find(|entry| entry.secret && entry.deployed_path() == path))
+}
+
+/// Replaces the targets of secret files and copies in `paths` by the files
+/// they are saved to, once their changes are collected.
+pub(crate) fn target_sources(
+    config: &Config,
+    manifest: &Manifest,
+    paths: Vec<PathBuf>,
+) -> Result<Vec<PathBuf>, RunCommandError> {
+    let copies = copies::entries(config, manifest)?;
+    let mut sources = Vec::new();
+
+    for path in paths {
+        let abs_path = std::env::current_dir()?.join(&path);
+        let canonical = std::fs::canonicalize(&abs_path);
+        let copy = copies.iter().find(|entry| {
+            let target = entry.deployed_path();
+            match &canonical {
+                Ok(canonical) => target == abs_path || target == *canonical,
+                Err(_) => target == abs_path,
+            }
+        });
+
+        match secret_entry(config, manifest, &path)?.or(copy) {
+            Some(entry) => {
+                deploy::collect(config, manifest, entry, false)?;
+                sources.push(config.worktree_path.join(&entry.path));
+            }
+            None => sources.push(path),
+        }
+    }
+
+    Ok(sources)
+}
 
-    config.lua.load(str.as_str()).exec()?;
+/// Writes the files of `manifest` that are deployed to their target and
+/// enforces their mode, then creates its links and removes the ones it does
+/// not declare anymore.
+pub(crate) fn deploy(
+    config: &Config,
+    manifest: &Manifest,
+) -> Result<(), RunCommandError> {
+    for entry in host_files(config, manifest)? {
+        deploy::deploy(config, manifest, entry)?;
+    }
+
+    for link in manifest.links.iter() {
+        links::deploy(config, link)?;
+    }
+    links::remove_undeclared(config, manifest)?;
+
+    Ok(())
+}
+
+/// The files declared by `manifest`, relative to the worktree. The declared
+/// files and directories that do not exist are reported and skipped.
+pub(crate) fn declared_files(
+    config: &Config,
+    manifest: &Manifest,
+) -> Result<Vec<PathBuf>, RunCommandError> {
+    let repo = &config.repo;
+    let index = repo.index()?;
+    let mut files = Vec::new();
+
+    for entry in host_files(config, manifest)? {
+        let path = &entry.path;
+        if config.worktree_path.join(path).exists()
+            || index.get_path(path, 0).is_some()
+        {
+            files.push(path.clone());
+        } else if !entry.optional
+            && deploy::state(config, manifest, entry)? != DeployState::Changed
+        {
+            log::warn!(
+                "The file {} declared in `init.lua' does not exist.",
+                path.to_str().unwrap()
+            );
+        }
+    }
+
+    for entry in copies::entries(config, manifest)? {
+        if config.worktree_path.join(&entry.path).exists() {
+            files.push(entry.path);
+        }
+    }
+
+    for directory in manifest.directories.iter() {
+        let abs_dir = config.worktree_path.join(&directory.path);
+        if abs_dir.is_dir() {
+            files.extend(directory.files(&config.worktree_path)?);
+        } else {
+            log::warn!(
+                "The directory {} declared in `init.lua' does not exist.",
+                directory.path.to_str().unwrap()
+            );
+        }
+    }
+
+    Ok(files)
+}
+
+/// Status of `path`, a declared file. The untracked files inside a directory
+/// that is not in the configuration yet are not found by git, they are new.
+pub(crate) fn file_status(
+    config: &Config,
+    path: &Path,
+) -> Result<Status, git2::Error> {
+    match config.repo.status_file(path) {
+        Err(err)
+            if err.code() == git2::ErrorCode::NotFound
+                && config.worktree_path.join(path).exists() =>
+        {
+            Ok(Status::WT_NEW)
+        }
+        res => res,
+    }
+}
+
+/// Stages the changes of the files declared by `manifest`. With `--dry-run`
+/// the changes are only made to the index in memory, for the next steps of the
+/// command to see them. The edits of rendered templates are applied to them
+/// when `reverse` is set.
+pub(crate) fn stage(
+    config: &Config,
+    manifest: &Manifest,
+    reverse: bool,
+) -> Result<(), RunCommandError> {
+    let repo = &config.repo;
+    let mut index = repo.index()?;
+
+    for entry in host_files(config, manifest)? {
+        deploy::collect(config, manifest, entry, reverse)?;
+    }
+    for entry in copies::entries(config, manifest)? {
+        deploy::collect(config, manifest, &entry, false)?;
+    }
+
+    for path in declared_files(config, manifest)? {
+        let status = file_status(config, &path)?;
+
+        if status.contains(Status::WT_DELETED) {
+            index.remove_path(&path)?;
+            if config.dry_run {
+                log::info!(
+                    "Would remove the file {} from your config.",
+                    path.to_str().unwrap()
+                );
+            } else {
+                log::info!(
+                    "The file {} was removed from your config.",
+                    path.to_str().unwrap()
+                );
+            }
+        } else if status.intersects(
+            Status::WT_NEW
+                | Status::WT_RENAMED
+                | Status::WT_MODIFIED
+                | Status::WT_TYPECHANGE,
+        ) {
+            index.add_path(&path)?;
+            if config.dry_run {
+                log::info!(
+                    "Would add the file {} to your config.",
+                    path.to_str().unwrap()
+                );
+            } else {
+                log::info!(
+                    "The file {} was added to your config.",
+                    path.to_str().unwrap()
+                );
+            }
+        }
+    }
+
+    if !config.dry_run {
+        index.write()?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,24 @@
+mod rekey_subcommand;
+
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{args::SecretSubCommand, config::Config};
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "Secret Command Error:"]
+pub(crate) enum SecretCommandError {
+    RekeyError(rekey_subcommand::RekeyError),
+}
+
+pub(crate) fn run(
+    sub_command: SecretSubCommand,
+    config: Config,
+) -> Result<(), SecretCommandError> {
+    match sub_command {
+        SecretSubCommand::Rekey { key_file } => {
+            rekey_subcommand::run(config, key_file)?
+        }
+    };
+
+    Ok(())
+}
@@ -0,0 +1,203 @@
+use std::{collections::HashMap, os::unix::fs::PermissionsExt, path::PathBuf};
+
+use chacha20poly1305::aead::{rand_core::RngCore, OsRng};
+use git2::{Index, Oid};
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{
+    backup,
+    config::{self, Config},
+    layers, revision,
+    secrets::{self, KEY_FILE_KEY},
+    utils,
+};
+
+/// Environment variable holding the new passphrase, it is asked for on the
+/// terminal if unset.
+const NEW_PASSPHRASE_VAR: &str = "RDM_NEW_PASSPHRASE";
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "rekey error:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum RekeyError {
+    ConfigError(config::ConfigError),
+    SecretError(secrets::SecretError),
+    BackupError(backup::BackupError),
+    #[doc_format(
+        format_str = "The secret files {} have unsaved changes, save them \
+                      with `rdm config save' first.",
+        _1
+    )]
+    #[no_from]
+    UnsavedSecrets(String),
+    #[doc_to_string]
+    GitError(git2::Error),
+    #[doc_to_string]
+    IoError(std::io::Error),
+    #[doc_to_string]
+    ExpandError(utils::ExpandError),
+}
+
+/// The new key material, read from `key_file` or a new passphrase. A random
+/// key is written to `key_file` if it does not exist.
+fn new_key_material(
+    key_file: &Option<PathBuf>,
+    dry_run: bool,
+) -> Result<Vec<u8>, RekeyError> {
+    match key_file {
+        Some(path) if path.is_file() => Ok(std::fs::read(path)?),
+        Some(path) => {
+            let mut key = vec![0u8; 32];
+            OsRng.fill_bytes(&mut key);
+
+            if dry_run {
+                log::info!(
+                    "Would write a new key to {}.",
+                    path.to_str().unwrap()
+                );
+                return Ok(key);
+            }
+
+            if let Some(parent) = path.parent() {
+                std::fs::create_dir_all(parent)?;
+            }
+            std::fs::write(path, &key)?;
+            std::fs::set_permissions(
+                path,
+                std::fs::Permissions::from_mode(0o600),
+            )?;
+            log::warn!(
+                "A new key was written to {}, keep a copy of it: the secret \
+                 files can not be decrypted without it.",
+                path.to_str().unwrap()
+            );
+
+            Ok(key)
+        }
+        None => match std::env::var(NEW_PASSPHRASE_VAR) {
+            Ok(passphrase) if passphrase.is_empty() => {
+                Err(secrets::SecretError::EmptyPassphrase.into())
+            }
+            Ok(passphrase) => Ok(passphrase.into_bytes()),
+            Err(_) => Ok(secrets::ask_passphrase(
+                "New passphrase of the secret files",
+                true,
+            )?),
+        },
+    }
+}
+
+/// Re-encrypts the secret files of every host and layer with a new
+/// passphrase, or with the key of `key_file`, and commits them on their
+/// branches.
+pub(super) fn run(
+    config: Config,
+    key_file: Option<String>,
+) -> Result<(), RekeyError> {
+    let _lock = config.lock()?;
+    let repo = &config.repo;
+
+    // The worktree is moved to the new commits, it must not have changes of
+    // its own to the secret files.
+    let unsaved: Vec<String> = layers::statuses(repo, false)?
+        .into_iter()
+        .map(|(path, _)| path)
+        .filter(|path| path.ends_with(secrets::EXTENSION))
+        .collect();
+    if !unsaved.is_empty() {
+        return Err(RekeyError::UnsavedSecrets(unsaved.join(", ")));
+    }
+
+    // Everything is decrypted before anything is written, a wrong key leaves
+    // the branches untouched. A file shared by several branches is only
+    // decrypted once.
+    let branches = secrets::saved_secrets(repo)?;
+    let mut plaintexts = HashMap::new();
+    for (_, files) in branches.iter() {
+        for (path, id, _) in files {
+            if !plaintexts.contains_key(id) {
+                let blob = repo.find_blob(*id)?;
+                let plaintext =
+                    secrets::decrypt(&config, path, blob.content())?;
+                plaintexts.insert(*id, plaintext);
+            }
+        }
+    }
+
+    let key_file = match key_file {
+        Some(path) => {
+            Some(std::env::current_dir()?.join(utils::full_expand(&path)?))
+        }
+        None => None,
+    };
+    let material = new_key_material(&key_file, config.dry_run)?;
+
+    if config.dry_run {
+        for (refname, files) in branches.iter() {
+            log::info!(
+                "Would re-encrypt {} secret file(s) on {}.",
+                files.len(),
+                refname
+            );
+        }
+        return Ok(());
+    }
+
+    let mut encrypted: HashMap<Oid, Oid> = HashMap::new();
+    for (id, plaintext) in plaintexts.iter() {
+        let ciphertext = secrets::encrypt_with(&material, plaintext)?;
+        encrypted.insert(*id, repo.blob(&ciphertext)?);
+    }
+
+    let old_tree = layers::head_tree(repo)?;
+    let sig = repo.signature()?;
+    for (refname, files) in branches.iter() {
+        let parent = repo.find_reference(refname)?.peel_to_commit()?;
+        let mut index = Index::new()?;
+        index.read_tree(&parent.tree()?)?;
+        for (path, id, mode) in files {
+            index.add(&layers::index_entry(path, encrypted[id], *mode))?;
+        }
+        let tree = repo.find_tree(index.write_tree_to(repo)?)?;
+
+        let msg = revision::message(
+            revision::of_commit(&parent)? + 1,
+            Some("rekey the secret files"),
+        );
+        repo.commit(Some(refname), &sig, &sig, &msg, &tree, &[&parent])?;
+    }
+
+    if let (Some(old_tree), Some(new_tree)) =
+        (old_tree, layers::head_tree(repo)?)
+    {
+        layers::update_worktree(
+            repo,
+            &config.config_path,
+            &old_tree,
+            &new_tree,
+            "rekeying",
+        )?;
+    }
+
+    let mut repo_config = repo.config()?;
+    match &key_file {
+        Some(path) => repo_config.set_str(
+            KEY_FILE_KEY,
+            &utils::portable_path(path.to_str().unwrap()),
+        )?,
+        None => match repo_config.remove(KEY_FILE_KEY) {
+            Err(err) if err.code() != git2::ErrorCode::NotFound => {
+                return Err(err.into())
+            }
+            _ => (),
+        },
+    }
+
+    log::info!(
+        "Re-encrypted {} secret file(s) on {} branch(es).",
+        branches.iter().map(|(_, files)| files.len()).sum::<usize>(),
+        branches.len()
+    );
+
+    Ok(())
+}
@@ -0,0 +1,240 @@
+use std::path::PathBuf;
+
+use argon2::Argon2;
+use chacha20poly1305::{
+    aead::{rand_core::RngCore, Aead, AeadCore, KeyInit, OsRng},
+    XChaCha20Poly1305, XNonce,
+};
+use git2::{BranchType, Oid, Repository};
+use rdm_macros::{FromError, ToDoc};
+
+use crate::{config::Config, layers, utils};
+
+/// Repository config key holding the path of the key file of the secret
+/// files, they are encrypted with a passphrase if it is not set.
+pub(crate) const KEY_FILE_KEY: &str = "rdm.secretKeyFile";
+
+/// Environment variable holding the passphrase of the secret files, it is
+/// asked for on the terminal if unset.
+pub(crate) const PASSPHRASE_VAR: &str = "RDM_PASSPHRASE";
+
+/// Extension of the encrypted files, added to the path of their target.
+pub(crate) const EXTENSION: &str = ".secret";
+
+/// Start of every encrypted file, followed by the salt of its key, its nonce
+/// and the ciphertext.
+const MAGIC: &[u8] = b"rdm-secret\x01";
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 24;
+
+#[derive(Debug, FromError, ToDoc)]
+#[doc_prefix = "Error with your secret files:"]
+#[doc_after_prefix = "pretty::RcDoc::line()"]
+pub(crate) enum SecretError {
+    #[doc_to_string]
+    IoError(std::io::Error),
+    #[doc_to_string]
+    GitError(git2::Error),
+    #[doc_to_string]
+    ExpandError(utils::ExpandError),
+    #[doc_format(
+        format_str = "The key file {} of the secret files does not exist.",
+        _1
+    )]
+    #[no_from]
+    KeyFileNotFound(String),
+    #[doc_format(
+        format_str = "No passphrase for the secret files, set `{}' or run rdm \
+                      in a terminal.",
+        _1
+    )]
+    #[no_from]
+    NoPassphrase(String),
+    #[doc_text = "The passphrase of the secret files can not be empty."]
+    EmptyPassphrase,
+    #[doc_text = "The passphrases do not match."]
+    PassphraseMismatch,
+    #[doc_format(format_str = "The file {} is not encrypted by rdm.", _1)]
+    #[no_from]
+    NotEncrypted(String),
+    #[doc_format(
+        format_str = "The file {} could not be decrypted, the key is wrong or \
+                      the file was altered.",
+        _1
+    )]
+    #[no_from]
+    DecryptionFailed(String),
+    #[doc_text = "The key of the secret files could not be derived."]
+    KeyDerivationFailed,
+    #[doc_text = "A secret file could not be encrypted."]
+    EncryptionFailed,
+}
+
+/// The key file of the secret files set in the repository config, if any.
+pub(crate) fn key_file(
+    repo: &Repository,
+) -> Result<Option<PathBuf>, SecretError> {
+    match repo.config()?.snapshot()?.get_string(KEY_FILE_KEY) {
+        Ok(path) => Ok(Some(PathBuf::from(utils::full_expand(&path)?))),
+        Err(err) if err.code() == git2::ErrorCode::NotFound => Ok(None),
+        Err(err) => Err(err.into()),
+    }
+}
+
+/// A secret file saved on a branch, as `(path, id, filemode)`.
+pub(crate) type SavedSecret = (String, Oid, u32);
+
+/// The local branches, hosts and layers, that hold secret files, with their
+/// secret files.
+pub(crate) fn saved_secrets(
+    repo: &Repository,
+) -> Result<Vec<(String, Vec<SavedSecret>)>, git2::Error> {
+    let mut branches = Vec::new();
+
+    for branch in repo.branches(Some(BranchType::Local))? {
+        let (branch, _) = branch?;
+        let files: Vec<SavedSecret> =
+            layers::blobs(&branch.get().peel_to_tree()?)?
+                .into_iter()
+                .filter(|(path, _, _)| path.ends_with(EXTENSION))
+                .collect();
+
+        if files.is_empty() {
+            continue;
+        }
+        if let Some(refname) = branch.get().name() {
+            branches.push((refname.to_string(), files));
+        }
+    }
+
+    Ok(branches)
+}
+
+/// Asks for a passphrase on the terminal, twice if `confirm` is set.
+pub(crate) fn ask_passphrase(
+    prompt: &str,
+    confirm: bool,
+) -> Result<Vec<u8>, SecretError> {
+    let term = console::Term::stderr();
+    if !term.is_term() {
+        return Err(SecretError::NoPassphrase(PASSPHRASE_VAR.to_string()));
+    }
+
+    term.write_str(&format!("{}: ", prompt))?;
+    let passphrase = term.read_secure_line()?;
+    if passphrase.is_empty() {
+        return Err(SecretError::EmptyPassphrase);
+    }
+
+    if confirm {
+        term.write_str("Confirm it: ")?;
+        if term.read_secure_line()? != passphrase {
+            return Err(SecretError::PassphraseMismatch);
+        }
+    }
+
+    Ok(passphrase.into_bytes())
+}
+
+/// Whether a secret file was saved or staged already, the passphrase of the
+/// first one is asked for twice.
+fn secrets_exist(repo: &Repository) -> Result<bool, git2::Error> {
+    let staged = repo
+        .index()?
+        .iter()
+        .any(|entry| String::from_utf8_lossy(&entry.path).ends_with(EXTENSION));
+
+    Ok(staged || !saved_secrets(repo)?.is_empty())
+}
+
+/// The secret the keys of the secret files are derived from: the content of
+/// the key file if one is set, the passphrase otherwise. It is only read once,
+/// and asked for twice if it is used to `encrypt` the first secret file.
+fn key_material(config: &Config, encrypt: bool) -> Result<&[u8], SecretError> {
+    if let Some(material) = config.secret_key.get() {
+        return Ok(material);
+    }
+
+    let material = match key_file(&config.repo)? {
+        Some(path) if !path.is_file() => {
+            return Err(SecretError::KeyFileNotFound(
+                path.to_string_lossy().to_string(),
+            ))
+        }
+        Some(path) => std::fs::read(path)?,
+        None => match std::env::var(PASSPHRASE_VAR) {
+            Ok(passphrase) if passphrase.is_empty() => {
+                return Err(SecretError::EmptyPassphrase)
+            }
+            Ok(passphrase) => passphrase.into_bytes(),
+            Err(_) => ask_passphrase(
+                "Passphrase of the secret files",
+                encrypt && !secrets_exist(&config.repo)?,
+            )?,
+        },
+    };
+
+    Ok(config.secret_key.get_or_init(|| material))
+}
+
+fn cipher(
+    material: &[u8],
+    salt: &[u8],
+) -> Result<XChaCha20Poly1305, SecretError> {
+    let mut key = [0u8; 32];
+    Argon2::default()
+        .hash_password_into(material, salt, &mut key)
+        .map_err(|_| SecretError::KeyDerivationFailed)?;
+
+    Ok(XChaCha20Poly1305::new(&key.into()))
+}
+
+/// Encrypts `plaintext` with a key derived from `material` and a new salt.
+pub(crate) fn encrypt_with(
+    material: &[u8],
+    plaintext: &[u8],
+) -> Result<Vec<u8>, SecretError> {
+    let mut salt = [0u8; SALT_LEN];
+    OsRng.fill_bytes(&mut salt);
+    let nonce = XChaCha20Poly1305::generate_nonce(&mut OsRng);
+
+    let ciphertext = cipher(material, &salt)?
+        .encrypt(&nonce, plaintext)
+        .map_err(|_| SecretError::EncryptionFailed)?;
+
+    Ok([MAGIC, &salt, &nonce, &ciphertext].concat())
+}
+
+/// Decrypts the content of the secret file `name`, encrypted with a key
+/// derived from `material`.
+pub(crate) fn decrypt_with(
+    material: &[u8],
+    name: &str,
+    encrypted: &[u8],
+) -> Result<Vec<u8>, SecretError> {
+    let rest = encrypted
+        .strip_prefix(MAGIC)
+        .filter(|rest| rest.len() >= SALT_LEN + NONCE_LEN)
+        .ok_or_else(|| SecretError::NotEncrypted(name.to_string()))?;
+    let (salt, rest) = rest.split_at(SALT_LEN);
+    let (nonce, ciphertext) = rest.split_at(NONCE_LEN);
+
+    cipher(material, salt)?
+        .decrypt(XNonce::from_slice(nonce), ciphertext)
+        .map_err(|_| SecretError::DecryptionFailed(name.to_string()))
+}
+
+pub(crate) fn encrypt(
+    config: &Config,
+    plaintext: &[u8],
+) -> Result<Vec<u8>, SecretError> {
+    encrypt_with(key_material(config, true)?, plaintext)
+}
+
+pub(crate) fn decrypt(
+    config: &Config,
+    name: &str,
+    encrypted: &[u8],
+) -> Result<Vec<u8>, SecretError> {
+    decrypt_with(key_material(config, false)?, name, encrypted)
+}
@@ -1,11 +1,121 @@
-pub fn full_expand(str: &str) -> String {
-    let mut expanded = shellexpand::full(&str).unwrap().to_string();
-    let mut new_expanded = shellexpand::full(&expanded).unwrap().to_string();
+use std::{
+    env::VarError,
+    fs::File,
+    io::Write,
+    path::{Path, PathBuf},
+};
+
+/// The XDG base directory variables that can be used in portable paths, with
+/// their default value.
+const XDG_VARS: [(&str, &str); 4] = [
+    ("XDG_CONFIG_HOME", "~/.config"),
+    ("XDG_DATA_HOME", "~/.local/share"),
+    ("XDG_STATE_HOME", "~/.local/state"),
+    ("XDG_CACHE_HOME", "~/.cache"),
+];
+
+/// A variable of a path that is not set, with its name.
+pub(crate) type ExpandError = shellexpand::LookupError<VarError>;
+
+/// The value of the environment variable `var`, an empty one is unset.
+fn env_var(var: &str) -> Result<String, VarError> {
+    std::env::var(var).and_then(|value| {
+        if value.is_empty() {
+            Err(VarError::NotPresent)
+        } else {
+            Ok(value)
+        }
+    })
+}
+
+fn expand(str: &str) -> Result<String, ExpandError> {
+    shellexpand::full_with_context(
+        str,
+        || env_var("HOME").ok(),
+        |var| match env_var(var) {
+            Ok(value) => Ok(Some(value)),
+            Err(err) => XDG_VARS
+                .iter()
+                .find(|(name, _)| *name == var)
+                .map(|(_, default)| Some(default.to_string()))
+                .ok_or(err),
+        },
+    )
+    .map(|expanded| expanded.to_string())
+}
+
+/// Expands `~` and the variables of `str` until nothing is left to expand, the
+/// unset or empty XDG base directory variables take their default value, and
+/// the other ones are an error.
+pub fn full_expand(str: &str) -> Result<String, ExpandError> {
+    let mut expanded = expand(str)?;
+    let mut new_expanded = expand(&expanded)?;
 
     while expanded != new_expanded {
         expanded = new_expanded;
-        new_expanded = shellexpand::full(&expanded).unwrap().to_string();
+        new_expanded = expand(&expanded)?;
     }
 
-    expanded
+    Ok(expanded)
+}
+
+/// Replaces the content of the file at `path` through a temporary file next
+/// to it, so that an interrupted write leaves the previous content.
+pub(crate) fn write_atomically(
+    path: &Path,
+    contents: &[u8],
+) -> Result<(), std::io::Error> {
+    let mut tmp_name = std::ffi::OsString::from(".");
+    tmp_name.push(path.file_name().unwrap_or_default());
+    tmp_name.push(".tmp");
+    let tmp_path = path.with_file_name(tmp_name);
+
+    let mut file = File::create(&tmp_path)?;
+    file.write_all(contents)?;
+    file.sync_all()?;
+
+    std::fs::rename(&tmp_path, path)
+}
+
+/// Replaces the start of the absolute `path` by the most specific of `~` and
+/// the XDG base directory variables, so that it can be expanded with
+/// `full_expand` on another machine. Returns `path` as is if it is not in any
+/// of them.
+pub(crate) fn portable_path(path: &str) -> String {
+    let abs_path =
+        std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path));
+    let mut prefixes = vec![("~".to_string(), std::env::var("HOME").ok())];
+    prefixes.extend(
+        XDG_VARS
+            .iter()
+            .map(|(var, _)| (format!("${}", var), std::env::var(var).ok())),
+    );
+
+    prefixes
+        .into_iter()
+        .filter_map(|(placeholder, value)| {
+            let value = std::fs::canonicalize(value?).ok()?;
+            let rest = abs_path.strip_prefix(&value).ok()?;
+            Some((value.components().count(), placeholder, rest.to_owned()))
+        })
+        .max_by_key(|(depth, _, _)| *depth)
+        .map(|(_, placeholder, rest)| {
+            if rest.as_os_str().is_empty() {
+                placeholder
+            } else {
+                format!("{}/{}", placeholder, rest.to_string_lossy())
+            }
+        })
+        .unwrap_or_else(|| path.to_string())
+}
+
+/// Returns `path` relative to `worktree`, `path` may not exist.
+pub(crate) fn worktree_relative(
+    path: &Path,
+    worktree: &Path,
+) -> Result<PathBuf, std::io::Error> {
+    let path = shellexpand::tilde(path.to_str().unwrap()).to_string();
+    let abs_path = std::env::current_dir()?.join(path);
+
+    Ok(pathdiff::diff_paths(&abs_path, worktree).unwrap_or(abs_path))
 }